@@ -1,7 +1,7 @@
 use proc_macro2::TokenStream;
 use quote::quote;
 
-#[proc_macro_derive(DynStruct)]
+#[proc_macro_derive(DynStruct, attributes(dyn_struct))]
 pub fn derive_dyn_struct(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = syn::parse_macro_input!(input as syn::DeriveInput);
 
@@ -22,7 +22,9 @@ macro_rules! err {
 fn expand(input: syn::DeriveInput) -> syn::Result<TokenStream> {
     match &input.data {
         syn::Data::Struct(struc) => {
-            check_repr(&input)?;
+            check_repr(&input, "DynStruct")?;
+            check_repr_hints(&input)?;
+            let options = Options::parse(&input.attrs)?;
 
             let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
 
@@ -52,21 +54,36 @@ fn expand(input: syn::DeriveInput) -> syn::Result<TokenStream> {
                 });
 
                 phantom_field = quote! {
-                    __DynStruct_phantom: std::marker::PhantomData<(#(#variables,)*)>
+                    __DynStruct_phantom: ::core::marker::PhantomData<(#(#variables,)*)>
                 };
-                phantom_init = quote! { __DynStruct_phantom: std::marker::PhantomData };
+                phantom_init = quote! { __DynStruct_phantom: ::core::marker::PhantomData };
             } else {
                 phantom_field = quote! {};
                 phantom_init = quote! {};
             };
 
+            // the header is archived like a regular struct
+            let single_attributes = if options.rkyv {
+                quote! {
+                    #[derive(
+                        dyn_struct::__private::rkyv::Archive,
+                        dyn_struct::__private::rkyv::Serialize,
+                        dyn_struct::__private::rkyv::Deserialize,
+                    )]
+                    #[rkyv(crate = dyn_struct::__private::rkyv)]
+                }
+            } else {
+                quote! {}
+            };
+
             let single_definition;
             let single_init;
             let single_idents: Vec<syn::Ident>;
             if matches!(struc.fields, syn::Fields::Named(_)) {
                 single_definition = quote! {
                     #[repr(C)]
-                    struct #single #impl_generics #where_clause {
+                    #single_attributes
+                    pub struct #single #impl_generics #where_clause {
                         #(#sized_fields,)*
                         #phantom_field
                     }
@@ -79,7 +96,8 @@ fn expand(input: syn::DeriveInput) -> syn::Result<TokenStream> {
             } else {
                 single_definition = quote! {
                     #[repr(C)]
-                    struct #single #impl_generics ( #(#sized_fields,)* #phantom_init ) #where_clause;
+                    #single_attributes
+                    pub struct #single #impl_generics ( #(#sized_fields,)* #phantom_init ) #where_clause;
                 };
                 single_idents = sized_fields
                     .iter()
@@ -89,11 +107,15 @@ fn expand(input: syn::DeriveInput) -> syn::Result<TokenStream> {
                 single_init = quote! { #single ( #(#single_idents,)*, #phantom_init ) };
             };
 
-            let sized_parameters = sized_fields.iter().enumerate().map(|(i, field)| {
-                let name = &single_idents[i];
-                let ty = &field.ty;
-                quote! { #name: #ty }
-            });
+            let sized_parameters: Vec<TokenStream> = sized_fields
+                .iter()
+                .enumerate()
+                .map(|(i, field)| {
+                    let name = &single_idents[i];
+                    let ty = &field.ty;
+                    quote! { #name: #ty }
+                })
+                .collect();
 
             let dynamic_type = match &dynamic_field.ty {
                 syn::Type::Slice(inner) => inner.elem.as_ref(),
@@ -109,22 +131,558 @@ fn expand(input: syn::DeriveInput) -> syn::Result<TokenStream> {
                 .clone()
                 .unwrap_or_else(|| syn::Ident::new("tail", span(dynamic_type)));
 
-            let struct_ident = &input.ident;
-            Ok(quote! {
-                impl #impl_generics #struct_ident #type_generics #where_clause {
-                    pub fn new<I>(#(#sized_parameters,)* #dynamic_name: I) -> Box<Self>
-                        where I: std::iter::IntoIterator<Item = #dynamic_type>,
-                              <I as std::iter::IntoIterator>::IntoIter: std::iter::ExactSizeIterator
+            // `DynStruct<Header, Tail>` places the tail after the padded size of the header, while
+            // the derived struct may start it inside the trailing padding of its last sized field.
+            // Their layouts only agree if the padding never reaches the next multiple of the tail's
+            // alignment, which we check whenever a header is created.
+            let layout_check = match sized_fields.last() {
+                Some(last) => {
+                    let member = match &last.ident {
+                        Some(ident) => syn::Member::Named(ident.clone()),
+                        None => syn::Member::Unnamed(syn::Index::from(sized_fields.len() - 1)),
+                    };
+                    let last_ty = &last.ty;
+                    let message = syn::LitStr::new(
+                        &format!(
+                            "the trailing padding after the last sized field of `{}` overlaps its tail; \
+                             try reordering the fields",
+                            input.ident
+                        ),
+                        input.ident.span(),
+                    );
+                    quote! {
+                        impl #impl_generics #single #type_generics #where_clause {
+                            const LAYOUT_CHECK: () = {
+                                let tail_align = ::core::mem::align_of::<#dynamic_type>();
+                                let end = ::core::mem::offset_of!(Self, #member) + ::core::mem::size_of::<#last_ty>();
+                                let size = ::core::mem::size_of::<Self>();
+                                ::core::assert!(end.div_ceil(tail_align) == size.div_ceil(tail_align), #message);
+                            };
+                        }
+                    }
+                }
+                None => quote! {
+                    impl #impl_generics #single #type_generics #where_clause {
+                        const LAYOUT_CHECK: () = ();
+                    }
+                },
+            };
+            // must be evaluated by every function that reinterprets a `DynStruct` as `Self`
+            let check_layout = quote! {
+                #[allow(clippy::let_unit_value)]
+                let () = <#single #type_generics>::LAYOUT_CHECK;
+            };
+            let single_init = quote! {{
+                #check_layout
+                #single_init
+            }};
+
+            let constructor = |name: &str, pointer: TokenStream, exact_size: bool| {
+                let name = syn::Ident::new(name, input.ident.span());
+                let exact_size_bound = if exact_size {
+                    quote! { <I as ::core::iter::IntoIterator>::IntoIter: ::core::iter::ExactSizeIterator }
+                } else {
+                    quote! {}
+                };
+                quote! {
+                    pub fn #name<I>(#(#sized_parameters,)* #dynamic_name: I) -> #pointer<Self>
+                        where I: ::core::iter::IntoIterator<Item = #dynamic_type>,
+                              #exact_size_bound
                     {
-                        #single_definition
+                        let header: #single #type_generics = #single_init;
+
+                        let dyn_struct = dyn_struct::DynStruct::#name(header, #dynamic_name);
+                        let ptr = #pointer::into_raw(dyn_struct);
+                        unsafe { #pointer::from_raw(ptr as *mut Self) }
+                    }
+                }
+            };
 
+            let new = constructor("new", quote! { dyn_struct::__private::Box }, true);
+            let new_rc = constructor("new_rc", quote! { dyn_struct::__private::Rc }, true);
+            let new_arc = constructor("new_arc", quote! { dyn_struct::__private::Arc }, true);
+            let new_arc = quote! { #[cfg(target_has_atomic = "ptr")] #new_arc };
+            let from_iter = constructor("from_iter", quote! { dyn_struct::__private::Box }, false);
+
+            let try_from_iter = quote! {
+                /// Build the value from an iterator of `Result`s, returning the first error.
+                ///
+                /// This mirrors `DynStruct::try_from_iter` and is deliberately not called
+                /// `try_new`: `DynStruct::try_new` reports an iterator of the wrong length or a
+                /// failed allocation instead, which the infallible constructors panic on.
+                pub fn try_from_iter<I, E>(#(#sized_parameters,)* #dynamic_name: I) -> ::core::result::Result<dyn_struct::__private::Box<Self>, E>
+                    where I: ::core::iter::IntoIterator<Item = ::core::result::Result<#dynamic_type, E>>
+                {
+                    let header: #single #type_generics = #single_init;
+
+                    let dyn_struct = dyn_struct::DynStruct::try_from_iter(header, #dynamic_name)?;
+                    let ptr = dyn_struct::__private::Box::into_raw(dyn_struct);
+                    ::core::result::Result::Ok(unsafe { dyn_struct::__private::Box::from_raw(ptr as *mut Self) })
+                }
+            };
+
+            let new_in = if options.new_in {
+                quote! {
+                    pub fn new_in<I, A>(#(#sized_parameters,)* #dynamic_name: I, allocator: A) -> dyn_struct::allocator::Box<Self, A>
+                        where I: ::core::iter::IntoIterator<Item = #dynamic_type>,
+                              <I as ::core::iter::IntoIterator>::IntoIter: ::core::iter::ExactSizeIterator,
+                              A: dyn_struct::allocator::Allocator
+                    {
                         let header: #single #type_generics = #single_init;
 
-                        let dyn_struct = dyn_struct::DynStruct::new(header, #dynamic_name);
-                        let ptr = std::boxed::Box::into_raw(dyn_struct);
-                        unsafe { std::boxed::Box::from_raw(ptr as *mut Self) }
+                        let dyn_struct = dyn_struct::DynStruct::new_in(header, #dynamic_name, allocator);
+                        let (ptr, allocator) = dyn_struct::allocator::Box::into_raw_with_allocator(dyn_struct);
+                        unsafe { dyn_struct::allocator::Box::from_raw_in(ptr as *mut Self, allocator) }
                     }
                 }
+            } else {
+                quote! {}
+            };
+
+            let new_thin_arc = quote! {
+                #[cfg(target_has_atomic = "ptr")]
+                pub fn new_thin_arc<I>(#(#sized_parameters,)* #dynamic_name: I) -> dyn_struct::ThinArc<#single #type_generics, #dynamic_type>
+                    where I: ::core::iter::IntoIterator<Item = #dynamic_type>,
+                          <I as ::core::iter::IntoIterator>::IntoIter: ::core::iter::ExactSizeIterator
+                {
+                    let header: #single #type_generics = #single_init;
+                    dyn_struct::ThinArc::new(header, #dynamic_name)
+                }
+            };
+
+            let new_in_arena = quote! {
+                #[allow(clippy::mut_from_ref)]
+                pub fn new_in_arena<'__arena, '__values, I>(arena: &'__arena dyn_struct::DynArena<'__values>, #(#sized_parameters,)* #dynamic_name: I) -> &'__arena mut Self
+                    where I: ::core::iter::IntoIterator<Item = #dynamic_type>,
+                          <I as ::core::iter::IntoIterator>::IntoIter: ::core::iter::ExactSizeIterator,
+                          #single #type_generics: '__values,
+                          #dynamic_type: '__values
+                {
+                    let header: #single #type_generics = #single_init;
+
+                    let dyn_struct = arena.alloc(header, #dynamic_name);
+                    unsafe { &mut *(dyn_struct as *mut dyn_struct::DynStruct<#single #type_generics, #dynamic_type> as *mut Self) }
+                }
+            };
+
+            let sized_types = sized_fields.iter().map(|field| &field.ty);
+            let single_pattern = if matches!(struc.fields, syn::Fields::Named(_)) {
+                quote! { #single { #(#single_idents,)* .. } }
+            } else {
+                quote! { #single ( #(#single_idents,)* .. ) }
+            };
+            let into_parts = quote! {
+                pub fn into_parts(self: dyn_struct::__private::Box<Self>) -> (#(#sized_types,)* dyn_struct::__private::Vec<#dynamic_type>) {
+                    let ptr = dyn_struct::__private::Box::into_raw(self);
+                    let dyn_struct = unsafe {
+                        dyn_struct::__private::Box::from_raw(ptr as *mut dyn_struct::DynStruct<#single #type_generics, #dynamic_type>)
+                    };
+                    let (header, #dynamic_name) = dyn_struct::DynStruct::into_parts(dyn_struct);
+                    let #single_pattern = header;
+                    (#(#single_idents,)* #dynamic_name)
+                }
+            };
+
+            let padding_check;
+            let bytes = if options.bytes {
+                let field_types: Vec<&syn::Type> =
+                    sized_fields.iter().map(|field| &field.ty).collect();
+                let message = syn::LitStr::new(
+                    &format!("the sized fields of `{}` contain padding", input.ident),
+                    input.ident.span(),
+                );
+                let dyn_struct_type =
+                    quote! { dyn_struct::DynStruct<#single #type_generics, #dynamic_type> };
+                let checks = quote! {
+                    #[allow(clippy::let_unit_value)]
+                    let () = <#single #type_generics>::LAYOUT_CHECK;
+                    #[allow(clippy::let_unit_value)]
+                    let () = <#single #type_generics>::PADDING_CHECK;
+                };
+                let pod_bounds = quote! {
+                    #(#field_types: dyn_struct::bytes::Pod,)*
+                    #dynamic_type: dyn_struct::bytes::Pod
+                };
+                padding_check = quote! {
+                    impl #impl_generics #single #type_generics #where_clause {
+                        const PADDING_CHECK: () = ::core::assert!(
+                            ::core::mem::size_of::<Self>() == 0 #(+ ::core::mem::size_of::<#field_types>())*,
+                            #message
+                        );
+                    }
+                };
+                quote! {
+                    pub fn from_bytes(bytes: &[u8]) -> ::core::result::Result<&Self, dyn_struct::DynStructError>
+                        where #pod_bounds
+                    {
+                        #checks
+                        let value = unsafe { <#dyn_struct_type>::from_bytes_unchecked(bytes)? };
+                        ::core::result::Result::Ok(<Self as dyn_struct::DynStructLike>::from_dyn_struct(value))
+                    }
+
+                    pub fn from_bytes_mut(bytes: &mut [u8]) -> ::core::result::Result<&mut Self, dyn_struct::DynStructError>
+                        where #pod_bounds
+                    {
+                        #checks
+                        let value = unsafe { <#dyn_struct_type>::from_bytes_mut_unchecked(bytes)? };
+                        ::core::result::Result::Ok(<Self as dyn_struct::DynStructLike>::from_dyn_struct_mut(value))
+                    }
+
+                    pub fn as_bytes(&self) -> &[u8]
+                        where #pod_bounds
+                    {
+                        #checks
+                        unsafe { <Self as dyn_struct::DynStructLike>::as_dyn_struct(self).as_bytes_unchecked() }
+                    }
+
+                    dyn_struct::__if_std! {
+                        #[cfg(target_endian = "little")]
+                        pub fn write_to<W>(&self, writer: W) -> dyn_struct::__private::io::Result<()>
+                            where W: dyn_struct::__private::io::Write, #pod_bounds
+                        {
+                            #checks
+                            unsafe { <Self as dyn_struct::DynStructLike>::as_dyn_struct(self).write_to_unchecked(writer) }
+                        }
+
+                        #[cfg(target_endian = "little")]
+                        pub fn read_from<R>(reader: R) -> dyn_struct::__private::io::Result<dyn_struct::__private::Box<Self>>
+                            where R: dyn_struct::__private::io::Read, #pod_bounds
+                        {
+                            Self::read_from_with_max_len(reader, dyn_struct::bytes::DEFAULT_MAX_LEN)
+                        }
+
+                        #[cfg(target_endian = "little")]
+                        pub fn read_from_with_max_len<R>(reader: R, max_len: usize) -> dyn_struct::__private::io::Result<dyn_struct::__private::Box<Self>>
+                            where R: dyn_struct::__private::io::Read, #pod_bounds
+                        {
+                            #checks
+                            let dyn_struct = unsafe { <#dyn_struct_type>::read_from_unchecked(reader, max_len)? };
+                            let ptr = dyn_struct::__private::Box::into_raw(dyn_struct);
+                            ::core::result::Result::Ok(unsafe { dyn_struct::__private::Box::from_raw(ptr as *mut Self) })
+                        }
+                    }
+                }
+            } else {
+                padding_check = quote! {};
+                quote! {}
+            };
+
+            let struct_ident = &input.ident;
+
+            let dyn_struct_type = quote! { dyn_struct::DynStruct<Self::Header, Self::Tail> };
+            let dyn_struct_like = quote! {
+                unsafe impl #impl_generics dyn_struct::DynStructLike for #struct_ident #type_generics #where_clause {
+                    type Header = #single #type_generics;
+                    type Tail = #dynamic_type;
+
+                    fn from_dyn_struct(value: &#dyn_struct_type) -> &Self {
+                        #check_layout
+                        unsafe { &*(value as *const #dyn_struct_type as *const Self) }
+                    }
+
+                    fn from_dyn_struct_mut(value: &mut #dyn_struct_type) -> &mut Self {
+                        #check_layout
+                        unsafe { &mut *(value as *mut #dyn_struct_type as *mut Self) }
+                    }
+
+                    fn as_dyn_struct(&self) -> &#dyn_struct_type {
+                        unsafe { &*(self as *const Self as *const #dyn_struct_type) }
+                    }
+
+                    fn as_dyn_struct_mut(&mut self) -> &mut #dyn_struct_type {
+                        unsafe { &mut *(self as *mut Self as *mut #dyn_struct_type) }
+                    }
+                }
+            };
+
+            let clone = if options.clone {
+                let members: Vec<syn::Member> = struc
+                    .fields
+                    .iter()
+                    .enumerate()
+                    .map(|(i, field)| match &field.ident {
+                        Some(ident) => syn::Member::Named(ident.clone()),
+                        None => syn::Member::Unnamed(syn::Index::from(i)),
+                    })
+                    .collect();
+                let (dynamic_member, sized_members) = members.split_last().unwrap();
+
+                let mut generics = input.generics.clone();
+                let predicates = &mut generics.make_where_clause().predicates;
+                for field in &sized_fields {
+                    let ty = &field.ty;
+                    predicates.push(syn::parse_quote! { #ty: ::core::clone::Clone });
+                }
+                let header_where_clause = generics.where_clause.clone();
+                let predicates = &mut generics.make_where_clause().predicates;
+                predicates.push(syn::parse_quote! { #dynamic_type: ::core::clone::Clone });
+                let (_, _, clone_where_clause) = generics.split_for_impl();
+
+                quote! {
+                    impl #impl_generics #struct_ident #type_generics #clone_where_clause {
+                        pub fn clone_box(&self) -> dyn_struct::__private::Box<Self> {
+                            Self::new(
+                                #(::core::clone::Clone::clone(&self.#sized_members),)*
+                                self.#dynamic_member.iter().cloned(),
+                            )
+                        }
+                    }
+
+                    impl #impl_generics ::core::clone::Clone for dyn_struct::__private::Box<#struct_ident #type_generics> #clone_where_clause {
+                        fn clone(&self) -> Self {
+                            #struct_ident::clone_box(self)
+                        }
+                    }
+
+                    impl #impl_generics ::core::clone::Clone for #single #type_generics #header_where_clause {
+                        fn clone(&self) -> Self {
+                            #(let #single_idents = ::core::clone::Clone::clone(&self.#sized_members);)*
+                            #single_init
+                        }
+                    }
+                }
+            } else {
+                quote! {}
+            };
+
+            let serde = if options.serde {
+                if !matches!(struc.fields, syn::Fields::Named(_)) {
+                    return Err(err!(
+                        &input.ident,
+                        "the `serde` option requires a struct with named fields"
+                    ));
+                }
+
+                let serde = quote! { dyn_struct::__private::serde };
+                let partial = syn::Ident::new(
+                    &format!("{}_DynStruct_Partial", input.ident),
+                    input.ident.span(),
+                );
+                let name = syn::LitStr::new(&input.ident.to_string(), input.ident.span());
+                let field_names: Vec<syn::LitStr> = single_idents
+                    .iter()
+                    .chain(Some(&dynamic_name))
+                    .map(|ident| syn::LitStr::new(&ident.to_string(), ident.span()))
+                    .collect();
+                let (tail_name, header_names) = field_names.split_last().unwrap();
+                let field_count = field_names.len();
+                let indices = 0..single_idents.len();
+                let indices2 = indices.clone();
+                let field_types: Vec<&syn::Type> =
+                    sized_fields.iter().map(|field| &field.ty).collect();
+
+                let mut generics = input.generics.clone();
+                let predicates = &mut generics.make_where_clause().predicates;
+                for ty in &field_types {
+                    predicates.push(syn::parse_quote! { #ty: #serde::Serialize });
+                }
+                predicates.push(syn::parse_quote! { #dynamic_type: #serde::Serialize });
+                let (_, _, serialize_where_clause) = generics.split_for_impl();
+
+                let mut generics = input.generics.clone();
+                generics.params.insert(0, syn::parse_quote! { '__de });
+                let predicates = &mut generics.make_where_clause().predicates;
+                for ty in &field_types {
+                    predicates.push(syn::parse_quote! { #ty: #serde::Deserialize<'__de> });
+                }
+                let header_generics = generics.clone();
+                let (header_impl_generics, _, header_where_clause) =
+                    header_generics.split_for_impl();
+                let predicates = &mut generics.make_where_clause().predicates;
+                predicates.push(syn::parse_quote! { #dynamic_type: #serde::Deserialize<'__de> });
+                let (deserialize_impl_generics, _, deserialize_where_clause) =
+                    generics.split_for_impl();
+
+                quote! {
+                    impl #impl_generics #serde::Serialize for #struct_ident #type_generics #serialize_where_clause {
+                        fn serialize<__S>(&self, serializer: __S) -> ::core::result::Result<__S::Ok, __S::Error>
+                            where __S: #serde::Serializer
+                        {
+                            use #serde::ser::SerializeStruct;
+                            let mut state = #serde::Serializer::serialize_struct(serializer, #name, #field_count)?;
+                            #(state.serialize_field(#header_names, &self.#single_idents)?;)*
+                            state.serialize_field(#tail_name, &self.#dynamic_name)?;
+                            state.end()
+                        }
+                    }
+
+                    #[allow(non_camel_case_types)]
+                    pub struct #partial #impl_generics #where_clause {
+                        #(#single_idents: ::core::option::Option<#field_types>,)*
+                        #phantom_field
+                    }
+
+                    impl #impl_generics ::core::default::Default for #partial #type_generics #where_clause {
+                        fn default() -> Self {
+                            #partial { #(#single_idents: ::core::option::Option::None,)* #phantom_init }
+                        }
+                    }
+
+                    impl #header_impl_generics dyn_struct::__private::DeserializeHeader<'__de> for #single #type_generics #header_where_clause {
+                        const NAME: &'static str = #name;
+                        const FIELDS: &'static [&'static str] = &[#(#field_names),*];
+
+                        type Partial = #partial #type_generics;
+
+                        fn deserialize_field<__A>(
+                            partial: &mut Self::Partial,
+                            index: usize,
+                            map: &mut __A,
+                        ) -> ::core::result::Result<(), __A::Error>
+                            where __A: #serde::de::MapAccess<'__de>
+                        {
+                            match index {
+                                #(#indices => dyn_struct::__private::set_field(
+                                    &mut partial.#single_idents,
+                                    #serde::de::MapAccess::next_value(map)?,
+                                    #header_names,
+                                ),)*
+                                _ => ::core::result::Result::Ok(()),
+                            }
+                        }
+
+                        fn next_element<__A>(
+                            partial: &mut Self::Partial,
+                            index: usize,
+                            seq: &mut __A,
+                        ) -> ::core::result::Result<bool, __A::Error>
+                            where __A: #serde::de::SeqAccess<'__de>
+                        {
+                            match index {
+                                #(#indices2 => {
+                                    partial.#single_idents = #serde::de::SeqAccess::next_element(seq)?;
+                                    ::core::result::Result::Ok(partial.#single_idents.is_some())
+                                })*
+                                _ => ::core::result::Result::Ok(false),
+                            }
+                        }
+
+                        fn is_complete(partial: &Self::Partial) -> bool {
+                            true #(&& partial.#single_idents.is_some())*
+                        }
+
+                        fn build<__E>(partial: Self::Partial) -> ::core::result::Result<Self, __E>
+                            where __E: #serde::de::Error
+                        {
+                            #(let #single_idents = partial.#single_idents
+                                .ok_or_else(|| <__E as #serde::de::Error>::missing_field(#header_names))?;)*
+                            ::core::result::Result::Ok(#single_init)
+                        }
+                    }
+
+                    impl #deserialize_impl_generics #serde::Deserialize<'__de> for dyn_struct::__private::Box<#struct_ident #type_generics> #deserialize_where_clause {
+                        fn deserialize<__D>(deserializer: __D) -> ::core::result::Result<Self, __D::Error>
+                            where __D: #serde::Deserializer<'__de>
+                        {
+                            #check_layout
+                            let dyn_struct = dyn_struct::__private::deserialize::<__D, #single #type_generics, #dynamic_type>(deserializer)?;
+                            let ptr = dyn_struct::__private::Box::into_raw(dyn_struct);
+                            ::core::result::Result::Ok(unsafe { dyn_struct::__private::Box::from_raw(ptr as *mut #struct_ident #type_generics) })
+                        }
+                    }
+                }
+            } else {
+                quote! {}
+            };
+
+            let rkyv = if options.rkyv {
+                let rkyv = quote! { dyn_struct::__private::rkyv };
+                let header = quote! { #single #type_generics };
+                let dyn_struct_type = quote! { dyn_struct::DynStruct<#header, #dynamic_type> };
+                let archived_type = quote! {
+                    #rkyv::boxed::ArchivedBox<dyn_struct::ArchivedDynStruct<#rkyv::Archived<#header>, #rkyv::Archived<#dynamic_type>>>
+                };
+
+                let mut archive_generics = input.generics.clone();
+                let predicates = &mut archive_generics.make_where_clause().predicates;
+                predicates.push(syn::parse_quote! { #header: #rkyv::Archive });
+                predicates.push(syn::parse_quote! { #dynamic_type: #rkyv::Archive });
+                let (_, _, archive_where_clause) = archive_generics.split_for_impl();
+
+                let mut generics = archive_generics.clone();
+                generics
+                    .params
+                    .push(syn::parse_quote! { __S: #rkyv::rancor::Fallible + ?Sized });
+                let predicates = &mut generics.make_where_clause().predicates;
+                predicates
+                    .push(syn::parse_quote! { #dyn_struct_type: #rkyv::SerializeUnsized<__S> });
+                let (serialize_impl_generics, _, serialize_where_clause) =
+                    generics.split_for_impl();
+
+                let mut generics = archive_generics.clone();
+                generics
+                    .params
+                    .push(syn::parse_quote! { __D: #rkyv::rancor::Fallible + ?Sized });
+                let predicates = &mut generics.make_where_clause().predicates;
+                predicates.push(syn::parse_quote! {
+                    #archived_type: #rkyv::Deserialize<dyn_struct::__private::Box<#dyn_struct_type>, __D>
+                });
+                let (deserialize_impl_generics, _, deserialize_where_clause) =
+                    generics.split_for_impl();
+
+                quote! {
+                    unsafe impl #impl_generics #rkyv::ptr_meta::Pointee for #struct_ident #type_generics #where_clause {
+                        type Metadata = usize;
+                    }
+
+                    impl #impl_generics #rkyv::ArchiveUnsized for #struct_ident #type_generics #archive_where_clause {
+                        type Archived = <#dyn_struct_type as #rkyv::ArchiveUnsized>::Archived;
+
+                        fn archived_metadata(&self) -> #rkyv::ArchivedMetadata<Self> {
+                            #rkyv::ArchiveUnsized::archived_metadata(dyn_struct::DynStructLike::as_dyn_struct(self))
+                        }
+                    }
+
+                    impl #serialize_impl_generics #rkyv::SerializeUnsized<__S> for #struct_ident #type_generics #serialize_where_clause {
+                        fn serialize_unsized(&self, serializer: &mut __S) -> ::core::result::Result<usize, __S::Error> {
+                            #rkyv::SerializeUnsized::serialize_unsized(dyn_struct::DynStructLike::as_dyn_struct(self), serializer)
+                        }
+                    }
+
+                    impl #deserialize_impl_generics #rkyv::Deserialize<dyn_struct::__private::Box<#struct_ident #type_generics>, __D>
+                        for #archived_type #deserialize_where_clause
+                    {
+                        fn deserialize(&self, deserializer: &mut __D) -> ::core::result::Result<dyn_struct::__private::Box<#struct_ident #type_generics>, __D::Error> {
+                            #check_layout
+                            let dyn_struct: dyn_struct::__private::Box<#dyn_struct_type> = #rkyv::Deserialize::deserialize(self, deserializer)?;
+                            let ptr = dyn_struct::__private::Box::into_raw(dyn_struct);
+                            ::core::result::Result::Ok(unsafe { dyn_struct::__private::Box::from_raw(ptr as *mut #struct_ident #type_generics) })
+                        }
+                    }
+                }
+            } else {
+                quote! {}
+            };
+
+            // The header type is defined once, next to the generated code, inside an anonymous
+            // constant. That way it can be named in signatures without polluting the namespace.
+            Ok(quote! {
+                const _: () = {
+                    #single_definition
+                    #layout_check
+                    #padding_check
+
+                    impl #impl_generics #struct_ident #type_generics #where_clause {
+                        #new
+                        #new_rc
+                        #new_arc
+                        #new_thin_arc
+                        #from_iter
+                        #try_from_iter
+                        #new_in
+                        #new_in_arena
+                        #into_parts
+                        #bytes
+                    }
+
+                    #dyn_struct_like
+
+                    #clone
+
+                    #serde
+
+                    #rkyv
+                };
             })
         }
         _ => Err(err!(
@@ -134,6 +692,145 @@ fn expand(input: syn::DeriveInput) -> syn::Result<TokenStream> {
     }
 }
 
+#[proc_macro_derive(HomogeneousHeader)]
+pub fn derive_homogeneous_header(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+
+    let output = match expand_homogeneous_header(input) {
+        Ok(output) => output,
+        Err(e) => e.to_compile_error(),
+    };
+
+    output.into()
+}
+
+fn expand_homogeneous_header(input: syn::DeriveInput) -> syn::Result<TokenStream> {
+    let struc = match &input.data {
+        syn::Data::Struct(struc) => struc,
+        _ => {
+            return Err(err!(
+                &input.ident,
+                "`HomogeneousHeader` can only be derived for structs"
+            ))
+        }
+    };
+    check_repr(&input, "HomogeneousHeader")?;
+
+    let first = struc.fields.iter().next().ok_or_else(|| {
+        err!(
+            &input.ident,
+            "cannot derive `HomogeneousHeader` for empty struct"
+        )
+    })?;
+
+    // the element type is the type of the first field, or its element type if it is an array
+    let element = match &first.ty {
+        syn::Type::Array(array) => array.elem.as_ref(),
+        ty => ty,
+    };
+    let element_tokens = quote! { #element }.to_string();
+
+    let mut counts = Vec::new();
+    let same_type = |ty: &syn::Type| quote! { #ty }.to_string() == element_tokens;
+    for field in struc.fields.iter() {
+        match &field.ty {
+            ty if same_type(ty) => counts.push(quote! { 1 }),
+            syn::Type::Array(array) if same_type(&array.elem) => {
+                let len = &array.len;
+                counts.push(quote! { #len });
+            }
+            ty => {
+                return Err(err!(
+                    ty,
+                    "expected `{}` or an array of `{}`",
+                    element_tokens,
+                    element_tokens
+                ))
+            }
+        }
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let message = syn::LitStr::new(
+        &format!(
+            "`{}` does not have the size and alignment of an array of `{}`; \
+             remove any `align` or `packed` representation",
+            ident, element_tokens
+        ),
+        ident.span(),
+    );
+
+    // Matching field types are not enough: `#[repr(align(N))]` or `#[repr(packed)]` change the
+    // size or alignment, which we can only check once the layout is known. Every method relying on
+    // the layout uses `LEN`, so they all evaluate the check.
+    Ok(quote! {
+        unsafe impl #impl_generics dyn_struct::HomogeneousHeader<#element> for #ident #type_generics #where_clause {
+            const LEN: usize = {
+                let len = 0 #(+ #counts)*;
+                ::core::assert!(
+                    ::core::mem::size_of::<Self>() == len * ::core::mem::size_of::<#element>()
+                        && ::core::mem::align_of::<Self>() == ::core::mem::align_of::<#element>(),
+                    #message
+                );
+                len
+            };
+        }
+    })
+}
+
+/// Options given through `#[dyn_struct(...)]` attributes.
+#[derive(Default)]
+struct Options {
+    /// Generate a `new_in` constructor taking a custom allocator.
+    new_in: bool,
+    /// Generate `clone_box` and implement `Clone` for `Box<Self>`.
+    clone: bool,
+    /// Generate `from_bytes`, `from_bytes_mut` and `as_bytes`, as well as `write_to` and
+    /// `read_from` if `dyn_struct` is built with `std`.
+    bytes: bool,
+    /// Implement `Serialize` for `Self` and `Deserialize` for `Box<Self>`.
+    serde: bool,
+    /// Implement the `rkyv` traits for `Self` and `Box<Self>`.
+    rkyv: bool,
+}
+
+impl Options {
+    fn parse(attrs: &[syn::Attribute]) -> syn::Result<Options> {
+        let mut options = Options::default();
+
+        for attr in attrs.iter().filter(|attr| attr.path.is_ident("dyn_struct")) {
+            let list = match attr.parse_meta()? {
+                syn::Meta::List(list) => list,
+                meta => return Err(err!(meta, "expected `#[dyn_struct(...)]`")),
+            };
+
+            for nested in list.nested.iter() {
+                match nested {
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("new_in") => {
+                        options.new_in = true
+                    }
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("clone") => {
+                        options.clone = true
+                    }
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("bytes") => {
+                        options.bytes = true
+                    }
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("serde") => {
+                        options.serde = true
+                    }
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("rkyv") => {
+                        options.rkyv = true
+                    }
+                    _ => return Err(err!(nested, "unknown `dyn_struct` option")),
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
 fn span<T: syn::spanned::Spanned>(value: &T) -> proc_macro2::Span {
     value.span()
 }
@@ -158,13 +855,14 @@ fn collect_fields(struc: &syn::DataStruct) -> syn::Result<(Vec<syn::Field>, syn:
     Ok((fields.into_iter().collect(), dynamic.into_value()))
 }
 
-fn check_repr(input: &syn::DeriveInput) -> syn::Result<()> {
+fn check_repr(input: &syn::DeriveInput, derive: &str) -> syn::Result<()> {
     if input.attrs.iter().any(|attr| is_repr_c(attr)) {
         Ok(())
     } else {
         Err(err!(
             &input.ident,
-            "`DynStruct` can only be derived for structs with `#[repr(C)]`"
+            "`{}` can only be derived for structs with `#[repr(C)]`",
+            derive
         ))
     }
 }
@@ -181,11 +879,30 @@ fn is_repr_c(attr: &syn::Attribute) -> bool {
     }
 }
 
+/// The derived `DynStruct` header is a plain `#[repr(C)]` struct, so any other representation
+/// hint (such as `packed` or `align`) would give `Self` a different layout than the `DynStruct` it
+/// is cast from.
+fn check_repr_hints(input: &syn::DeriveInput) -> syn::Result<()> {
+    for attr in input.attrs.iter().filter(|attr| attr.path.is_ident("repr")) {
+        let hints = attr.parse_args_with(
+            syn::punctuated::Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated,
+        )?;
+        for hint in hints {
+            if !matches!(&hint, syn::Meta::Path(path) if path.is_ident("C")) {
+                return Err(err!(
+                    hint,
+                    "`DynStruct` can only be derived for structs with exactly `#[repr(C)]`, without `packed`, `align` or any other representation"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
 fn find_ident(tokens: TokenStream) -> Option<syn::Ident> {
     tokens.into_iter().find_map(|tree| match tree {
-        quote::__private::TokenTree::Group(group) => find_ident(group.stream()),
-        quote::__private::TokenTree::Ident(ident) => Some(ident.clone()),
+        proc_macro2::TokenTree::Group(group) => find_ident(group.stream()),
+        proc_macro2::TokenTree::Ident(ident) => Some(ident.clone()),
         _ => None,
     })
 }
-
@@ -0,0 +1,175 @@
+//! Constructors that allocate using a custom [`Allocator`].
+//!
+//! On nightly, enable the `allocator_api` feature to use the unstable `Allocator` trait and `Box`
+//! from the standard library. On stable, enable the `allocator-api2` feature instead, which uses
+//! the identical API provided by the [`allocator-api2`](https://docs.rs/allocator-api2) crate.
+
+#[cfg(feature = "allocator_api")]
+pub use {alloc::boxed::Box, core::alloc::Allocator};
+
+#[cfg(all(feature = "allocator-api2", not(feature = "allocator_api")))]
+pub use allocator_api2::{alloc::Allocator, boxed::Box};
+
+use crate::{dyn_struct_ptr, Allocation, Chunk, DynStruct, DynStructError};
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+impl<Header, Tail> DynStruct<Header, Tail> {
+    /// Same as [`DynStruct::new`], but allocates using `allocator`.
+    #[inline]
+    pub fn new_in<I, A>(header: Header, tail: I, allocator: A) -> Box<Self, A>
+    where
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+        A: Allocator,
+    {
+        Self::write_iter::<AllocatorBox<A>, I>(header, tail, allocator)
+            .unwrap_or_else(|error| error.raise::<I::IntoIter>())
+    }
+
+    /// Same as [`DynStruct::try_new`], but allocates using `allocator`.
+    #[inline]
+    pub fn try_new_in<I, A>(
+        header: Header,
+        tail: I,
+        allocator: A,
+    ) -> Result<Box<Self, A>, DynStructError>
+    where
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+        A: Allocator,
+    {
+        Self::write_iter::<AllocatorBox<A>, I>(header, tail, allocator)
+    }
+
+    /// Same as [`DynStruct::from_slice`], but allocates using `allocator`.
+    pub fn from_slice_in<A>(header: Header, tail: &[Tail], allocator: A) -> Box<Self, A>
+    where
+        Tail: Copy,
+        A: Allocator,
+    {
+        Self::write_slice::<AllocatorBox<A>>(header, tail, allocator)
+            .unwrap_or_else(|error| error.raise::<()>())
+    }
+}
+
+/// Memory from a custom allocator, owned by a `Box<_, A>` once initialized.
+struct AllocatorBox<A: Allocator> {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    allocator: A,
+}
+
+unsafe impl<Header, Tail, A: Allocator> Allocation<Header, Tail> for AllocatorBox<A> {
+    type Output = Box<DynStruct<Header, Tail>, A>;
+    type Allocator = A;
+
+    #[inline]
+    fn new(len: usize, allocator: A) -> Result<Self, DynStructError> {
+        let layout = DynStruct::<Header, Tail>::layout(len)?;
+
+        let ptr = if allocates(layout) {
+            allocator
+                .allocate(layout)
+                .map_err(|_| DynStructError::AllocError(layout))?
+                .cast()
+        } else {
+            NonNull::<Chunk<Header, Tail>>::dangling().cast()
+        };
+
+        Ok(AllocatorBox {
+            ptr,
+            layout,
+            allocator,
+        })
+    }
+
+    #[inline]
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    #[inline]
+    unsafe fn finish(self, len: usize) -> Self::Output {
+        let ptr = dyn_struct_ptr(self.ptr.as_ptr(), len);
+        let allocator = core::ptr::read(&self.allocator);
+        // ownership of the memory is transferred to the `Box`
+        core::mem::forget(self);
+        Box::from_raw_in(ptr, allocator)
+    }
+}
+
+impl<A: Allocator> Drop for AllocatorBox<A> {
+    fn drop(&mut self) {
+        if allocates(self.layout) {
+            unsafe { self.allocator.deallocate(self.ptr, self.layout) }
+        }
+    }
+}
+
+/// Returns `true` if the memory for a value with the layout has to come from the allocator.
+///
+/// The `Box` from the standard library never deallocates zero-sized values, so just like
+/// `GlobalBox`, we use a dangling pointer for those. The `Box` from `allocator-api2` deallocates
+/// every value (which `Allocator`s accept even if zero-sized), so there we always allocate.
+#[inline]
+fn allocates(layout: Layout) -> bool {
+    layout.size() != 0 || cfg!(not(feature = "allocator_api"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, System};
+    use std::cell::Cell;
+
+    #[cfg(feature = "allocator_api")]
+    use std::alloc::AllocError;
+
+    #[cfg(all(feature = "allocator-api2", not(feature = "allocator_api")))]
+    use allocator_api2::alloc::AllocError;
+
+    /// Forwards to the system allocator while keeping track of the number of live allocations.
+    #[derive(Default)]
+    struct Tracking {
+        live: Cell<isize>,
+    }
+
+    unsafe impl Allocator for &Tracking {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.live.set(self.live.get() + 1);
+            let ptr = if layout.size() == 0 {
+                layout.align() as *mut u8
+            } else {
+                unsafe { System.alloc(layout) }
+            };
+            let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+            Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.live.set(self.live.get() - 1);
+            if layout.size() != 0 {
+                System.dealloc(ptr.as_ptr(), layout)
+            }
+        }
+    }
+
+    #[test]
+    fn custom_allocator() {
+        let tracking = Tracking::default();
+
+        let value = DynStruct::new_in((1u8, 2u16), [3u32, 4, 5], &tracking);
+        assert_eq!(value.header, (1, 2));
+        assert_eq!(&value.tail, &[3, 4, 5]);
+        assert_eq!(tracking.live.get(), 1);
+        drop(value);
+        assert_eq!(tracking.live.get(), 0);
+
+        // zero-sized values must not leak, whichever `Box` is used
+        let zero = DynStruct::from_slice_in((), &[(), ()], &tracking);
+        assert_eq!(&zero.tail, &[(), ()]);
+        drop(zero);
+        assert_eq!(tracking.live.get(), 0);
+    }
+}
@@ -0,0 +1,330 @@
+//! An arena that many `DynStruct`s can be allocated in and freed together.
+
+use crate::{dyn_struct_ptr, Allocation, Chunk, DynStruct, DynStructError};
+use alloc::alloc::{alloc, dealloc, Layout};
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::marker::PhantomData;
+use core::mem::needs_drop;
+use core::ptr::NonNull;
+
+/// An arena for allocating `DynStruct`s of any type, all of which are freed at once when the
+/// arena is dropped.
+///
+/// Values are placed one after the other into large blocks of memory, so allocating one only takes
+/// a pointer increment most of the time, without going through the global allocator. Their
+/// destructors (if any) run when the arena is dropped, in the order the values were allocated.
+///
+/// The lifetime `'a` bounds the values that can be allocated, which must outlive the arena since
+/// their destructors run only once the arena is dropped.
+///
+/// ```
+/// # use dyn_struct::{DynArena, DynStruct};
+/// let arena = DynArena::new();
+/// let first = arena.alloc(String::from("first"), [1u32, 2, 3]);
+/// let second = arena.alloc_from_slice(String::from("second"), &first.tail[1..]);
+///
+/// first.tail[0] = 0;
+/// assert_eq!(&first.tail, &[0, 2, 3]);
+/// assert_eq!(second.header, "second");
+/// assert_eq!(&second.tail, &[2, 3]);
+/// ```
+pub struct DynArena<'a> {
+    /// Every block allocated so far. New values are placed in the last one.
+    blocks: RefCell<Vec<Block>>,
+    /// The start of the unused memory in the last block.
+    next: Cell<*mut u8>,
+    /// The end of the last block.
+    end: Cell<*mut u8>,
+    /// The values which need to be dropped together with the arena.
+    drops: RefCell<Vec<DropEntry>>,
+    /// Invariant, so that an arena cannot be coerced into accepting shorter-lived values.
+    _values: PhantomData<fn(&'a ()) -> &'a ()>,
+}
+
+/// The size of the first block. Each following block is twice as large as the previous one, up to
+/// `MAX_BLOCK_SIZE`, unless a single value requires more.
+const FIRST_BLOCK_SIZE: usize = 4096;
+const MAX_BLOCK_SIZE: usize = 1 << 20;
+
+/// The alignment of every block, so that the first value placed in it rarely needs any padding.
+const BLOCK_ALIGN: usize = 16;
+
+struct Block {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+/// A `DynStruct` of some type which is dropped together with the arena.
+struct DropEntry {
+    ptr: *mut u8,
+    len: usize,
+    drop: unsafe fn(*mut u8, usize),
+}
+
+unsafe fn drop_dyn_struct<Header, Tail>(ptr: *mut u8, len: usize) {
+    core::ptr::drop_in_place(dyn_struct_ptr::<Header, Tail>(ptr, len))
+}
+
+impl<'a> DynArena<'a> {
+    /// Create an empty arena. Does not allocate until the first value is allocated in it.
+    #[inline]
+    pub const fn new() -> Self {
+        DynArena {
+            blocks: RefCell::new(Vec::new()),
+            next: Cell::new(core::ptr::null_mut()),
+            end: Cell::new(core::ptr::null_mut()),
+            drops: RefCell::new(Vec::new()),
+            _values: PhantomData,
+        }
+    }
+
+    /// Allocate a new `DynStruct` in the arena. Initialized lazily using an iterator.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<Header, Tail, I>(&self, header: Header, tail: I) -> &mut DynStruct<Header, Tail>
+    where
+        Header: 'a,
+        Tail: 'a,
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        self.try_alloc(header, tail)
+            .unwrap_or_else(|error| error.raise::<I::IntoIter>())
+    }
+
+    /// Same as [`DynArena::alloc`], but returns an error instead of panicking if the iterator
+    /// yields a different number of items than it reports, and instead of aborting if the
+    /// allocation fails or its size overflows.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn try_alloc<Header, Tail, I>(
+        &self,
+        header: Header,
+        tail: I,
+    ) -> Result<&mut DynStruct<Header, Tail>, DynStructError>
+    where
+        Header: 'a,
+        Tail: 'a,
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        DynStruct::write_iter::<ArenaSlot<'_, 'a, Header, Tail>, I>(header, tail, self)
+    }
+
+    /// Allocate a new `DynStruct` in the arena, copying the tail from a slice.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_from_slice<Header, Tail>(
+        &self,
+        header: Header,
+        tail: &[Tail],
+    ) -> &mut DynStruct<Header, Tail>
+    where
+        Header: 'a,
+        Tail: Copy + 'a,
+    {
+        DynStruct::write_slice::<ArenaSlot<'_, 'a, Header, Tail>>(header, tail, self)
+            .unwrap_or_else(|error| error.raise::<()>())
+    }
+
+    /// Returns the total number of bytes in the blocks allocated by the arena.
+    pub fn allocated_bytes(&self) -> usize {
+        let blocks = self.blocks.borrow();
+        blocks.iter().map(|block| block.layout.size()).sum()
+    }
+
+    /// Reserve memory for a value with the given (non-zero sized) layout.
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, DynStructError> {
+        let next = self.next.get();
+        let available = self.end.get() as usize - next as usize;
+        let padding = next.align_offset(layout.align());
+        if padding <= available && layout.size() <= available - padding {
+            let start = unsafe { next.add(padding) };
+            self.next.set(unsafe { start.add(layout.size()) });
+            return Ok(unsafe { NonNull::new_unchecked(start) });
+        }
+
+        // the value does not fit in the current block, so start a new one
+        let mut blocks = self.blocks.borrow_mut();
+        let growth = match blocks.last() {
+            Some(block) => usize::min(block.layout.size().saturating_mul(2), MAX_BLOCK_SIZE),
+            None => FIRST_BLOCK_SIZE,
+        };
+        let block_layout = Layout::from_size_align(
+            usize::max(layout.size(), growth),
+            usize::max(layout.align(), BLOCK_ALIGN),
+        )
+        .map_err(|_| DynStructError::SizeOverflow)?;
+        let ptr = NonNull::new(unsafe { alloc(block_layout) })
+            .ok_or(DynStructError::AllocError(block_layout))?;
+        blocks.push(Block {
+            ptr,
+            layout: block_layout,
+        });
+
+        unsafe {
+            self.next.set(ptr.as_ptr().add(layout.size()));
+            self.end.set(ptr.as_ptr().add(block_layout.size()));
+        }
+        Ok(ptr)
+    }
+}
+
+impl Default for DynArena<'_> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for DynArena<'_> {
+    fn drop(&mut self) {
+        // the blocks are only freed afterwards, even if one of the destructors panics
+        for entry in self.drops.get_mut().drain(..) {
+            unsafe { (entry.drop)(entry.ptr, entry.len) }
+        }
+    }
+}
+
+/// Memory reserved in a [`DynArena`], which hands out a reference to the `DynStruct` once
+/// initialized.
+struct ArenaSlot<'b, 'a, Header, Tail> {
+    arena: &'b DynArena<'a>,
+    ptr: NonNull<u8>,
+    _value: PhantomData<&'b mut DynStruct<Header, Tail>>,
+}
+
+unsafe impl<'b, 'a, Header: 'a, Tail: 'a> Allocation<Header, Tail>
+    for ArenaSlot<'b, 'a, Header, Tail>
+{
+    type Output = &'b mut DynStruct<Header, Tail>;
+    type Allocator = &'b DynArena<'a>;
+
+    fn new(len: usize, arena: Self::Allocator) -> Result<Self, DynStructError> {
+        let layout = DynStruct::<Header, Tail>::layout(len)?;
+        let ptr = if layout.size() == 0 {
+            NonNull::<Chunk<Header, Tail>>::dangling().cast()
+        } else {
+            arena.allocate(layout)?
+        };
+
+        Ok(ArenaSlot {
+            arena,
+            ptr,
+            _value: PhantomData,
+        })
+    }
+
+    #[inline]
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    #[inline]
+    unsafe fn finish(self, len: usize) -> Self::Output {
+        let ptr = self.ptr.as_ptr();
+        if needs_drop::<DynStruct<Header, Tail>>() {
+            self.arena.drops.borrow_mut().push(DropEntry {
+                ptr,
+                len,
+                drop: drop_dyn_struct::<Header, Tail>,
+            });
+        }
+        &mut *dyn_struct_ptr(ptr, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use alloc::string::String;
+
+    #[test]
+    fn alloc() {
+        let arena = DynArena::new();
+        let mut values = Vec::new();
+        for i in 0..1000u64 {
+            values.push(arena.alloc((i as u8, i), 0..(i % 7) as u32));
+        }
+        // one block did not fit all of them
+        assert!(arena.blocks.borrow().len() > 1);
+        assert!(arena.drops.borrow().is_empty());
+
+        for (i, value) in values.iter().enumerate() {
+            let i = i as u64;
+            assert_eq!(value.header, (i as u8, i));
+            assert!(value.tail.iter().copied().eq(0..(i % 7) as u32));
+            assert_eq!(value as *const _ as *const u8 as usize % 8, 0);
+        }
+
+        let large = arena.alloc_from_slice(1u8, &[7u128; 10_000][..]);
+        assert_eq!(large.tail.len(), 10_000);
+        assert_eq!(large as *const _ as *const u8 as usize % 16, 0);
+        assert!(arena.allocated_bytes() >= 160_000);
+
+        let empty = arena.alloc((), core::iter::empty::<()>());
+        assert!(empty.tail.is_empty());
+    }
+
+    #[test]
+    fn drop_values() {
+        let counter = Rc::new(());
+        let arena = DynArena::new();
+        for len in 0..100 {
+            arena.alloc(String::from("header"), vec![counter.clone(); len % 5]);
+        }
+        assert_eq!(Rc::strong_count(&counter), 1 + 200);
+
+        // nested allocations while writing the tail
+        let outer = arena.alloc(
+            counter.clone(),
+            (0..3).map(|i| arena.alloc(i, [counter.clone()]).header),
+        );
+        assert_eq!(&outer.tail, &[0, 1, 2]);
+        assert_eq!(Rc::strong_count(&counter), 1 + 200 + 4);
+
+        drop(arena);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn lying_iterator() {
+        struct Liar<I>(I);
+
+        impl<I: Iterator> Iterator for Liar<I> {
+            type Item = I::Item;
+
+            fn next(&mut self) -> Option<I::Item> {
+                self.0.next()
+            }
+        }
+
+        impl<I: Iterator> ExactSizeIterator for Liar<I> {
+            fn len(&self) -> usize {
+                2
+            }
+        }
+
+        let counter = Rc::new(());
+        let arena = DynArena::new();
+        let error = arena
+            .try_alloc((), Liar(vec![counter.clone(); 1].into_iter()))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            DynStructError::LengthMismatch {
+                expected: 2,
+                actual: 1
+            }
+        );
+        assert_eq!(Rc::strong_count(&counter), 1);
+        assert!(arena.drops.borrow().is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+//! Zero-copy conversions between `DynStruct`s and bytes.
+//!
+//! Enable the `bytemuck` feature to use these with types implementing [`bytemuck::Pod`], or the
+//! `zerocopy` feature to use them with types implementing `zerocopy::{FromBytes, IntoBytes,
+//! Immutable}`. If both features are enabled, a type has to implement both sets of traits: cargo
+//! features are unified across the dependency graph, so accepting either set would make a type
+//! that only implements one of them compile depending on which other crates are built, while a
+//! `Pod` bound has to mean the same thing for every crate using it.
+//!
+//! With the `std` feature, `DynStruct::write_to` and `DynStruct::read_from` also transfer
+//! such values through `std::io`, in a format that is the little-endian memory layout of the
+//! `DynStruct`. Since plain old data cannot be byte-swapped generically, they are deliberately
+//! only available on little-endian targets, rather than writing a different format on big-endian
+//! ones.
+
+use crate::{dyn_struct_ptr, DynStruct, DynStructError};
+use core::mem::size_of;
+
+#[cfg(all(feature = "std", target_endian = "little"))]
+mod io;
+mod records;
+
+#[cfg(all(feature = "std", target_endian = "little"))]
+pub use self::io::DEFAULT_MAX_LEN;
+pub use self::records::{DynStructRecordWriter, DynStructRecords, DynStructRecordsMut};
+
+/// Plain old data: types without padding that are valid for any bit pattern.
+///
+/// Implemented for every type implementing `bytemuck::Pod` and/or the corresponding `zerocopy`
+/// traits, depending on the enabled features (see the [module documentation](self)), so it never
+/// has to be implemented manually.
+///
+/// # Safety
+///
+/// The type must not contain padding or interior mutability, and any bit pattern must be a valid
+/// value of the type.
+pub unsafe trait Pod: Copy + 'static {}
+
+#[cfg(all(feature = "bytemuck", not(feature = "zerocopy")))]
+unsafe impl<T: bytemuck::Pod> Pod for T {}
+
+#[cfg(all(feature = "zerocopy", not(feature = "bytemuck")))]
+unsafe impl<T> Pod for T where
+    T: zerocopy::FromBytes + zerocopy::IntoBytes + zerocopy::Immutable + Copy + 'static
+{
+}
+
+#[cfg(all(feature = "bytemuck", feature = "zerocopy"))]
+unsafe impl<T> Pod for T where
+    T: bytemuck::Pod + zerocopy::FromBytes + zerocopy::IntoBytes + zerocopy::Immutable
+{
+}
+
+impl<Header: Pod, Tail: Pod> DynStruct<Header, Tail> {
+    /// View bytes as a `DynStruct` (this does not copy). Returns an error if the bytes are not
+    /// aligned for the `DynStruct`, or if their number is not the size of a `DynStruct` with some
+    /// length of the tail (see [`DynStruct::layout_for`]).
+    pub fn from_bytes(bytes: &[u8]) -> Result<&Self, DynStructError> {
+        unsafe { Self::from_bytes_unchecked(bytes) }
+    }
+
+    /// Same as [`DynStruct::from_bytes`], but returns a mutable view. Requires the `DynStruct` to
+    /// not contain any padding (see [`DynStruct::as_bytes`]), as writes to it would otherwise
+    /// leave uninitialized bytes behind.
+    pub fn from_bytes_mut(bytes: &mut [u8]) -> Result<&mut Self, DynStructError> {
+        unsafe { Self::from_bytes_mut_unchecked(bytes) }
+    }
+
+    /// View the `DynStruct` as bytes (this does not copy).
+    ///
+    /// Fails to compile if the `DynStruct` may contain padding, which is the case if there are
+    /// padding bytes between the header and the tail, or if the size of the header or the tail
+    /// elements is not a multiple of the alignment of the `DynStruct`.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { self.as_bytes_unchecked() }
+    }
+}
+
+#[doc(hidden)]
+impl<Header, Tail> DynStruct<Header, Tail> {
+    const NO_PADDING: () = assert!(
+        Self::tail_offset() == size_of::<Header>()
+            && size_of::<Header>().is_multiple_of(Self::align())
+            && size_of::<Tail>().is_multiple_of(Self::align()),
+        "`DynStruct` contains padding"
+    );
+
+    /// Implementation of [`DynStruct::from_bytes`], also used by the `DynStruct` derive.
+    ///
+    /// # Safety
+    ///
+    /// Any bit pattern has to be a valid `DynStruct<Header, Tail>`, which must not contain
+    /// interior mutability.
+    pub unsafe fn from_bytes_unchecked(bytes: &[u8]) -> Result<&Self, DynStructError> {
+        let len = Self::len_for_bytes(bytes)?;
+        Ok(&*dyn_struct_ptr(bytes.as_ptr() as *mut u8, len))
+    }
+
+    /// Implementation of [`DynStruct::from_bytes_mut`], also used by the `DynStruct` derive.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`DynStruct::from_bytes_unchecked`]. Additionally, the header and tail may not
+    /// contain any padding themselves.
+    pub unsafe fn from_bytes_mut_unchecked(bytes: &mut [u8]) -> Result<&mut Self, DynStructError> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::NO_PADDING;
+        let len = Self::len_for_bytes(bytes)?;
+        Ok(&mut *dyn_struct_ptr(bytes.as_mut_ptr(), len))
+    }
+
+    /// Implementation of [`DynStruct::as_bytes`], also used by the `DynStruct` derive.
+    ///
+    /// # Safety
+    ///
+    /// The header and tail may not contain any padding themselves.
+    pub unsafe fn as_bytes_unchecked(&self) -> &[u8] {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::NO_PADDING;
+        core::slice::from_raw_parts(
+            (self as *const Self).cast::<u8>(),
+            core::mem::size_of_val(self),
+        )
+    }
+
+    /// The length of the tail of a `DynStruct` occupying exactly the given bytes.
+    fn len_for_bytes(bytes: &[u8]) -> Result<usize, DynStructError> {
+        let align = Self::align();
+        if !(bytes.as_ptr() as usize).is_multiple_of(align) {
+            return Err(DynStructError::Misaligned { align });
+        }
+
+        let size = bytes.len();
+        let len = if size_of::<Tail>() == 0 {
+            // the length cannot be recovered from the size, so only allow an empty tail
+            0
+        } else {
+            Self::len_for_size(size).ok_or(DynStructError::SizeMismatch { size })?
+        };
+        match Self::layout_for(len) {
+            Ok(layout) if layout.size() == size => Ok(len),
+            _ => Err(DynStructError::SizeMismatch { size }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes aligned for any of the types used in the tests.
+    #[repr(C, align(8))]
+    struct Aligned<const N: usize>([u8; N]);
+
+    #[test]
+    fn from_bytes() {
+        let mut bytes = Aligned([1, 0, 0, 0, 2, 0, 3, 0, 4, 0, 5, 0]);
+
+        let value = DynStruct::<u32, u16>::from_bytes(&bytes.0).unwrap();
+        assert_eq!(value.header, u32::from_ne_bytes([1, 0, 0, 0]));
+        assert_eq!(value.tail.len(), 4);
+
+        let value = DynStruct::<[u16; 2], u16>::from_bytes_mut(&mut bytes.0).unwrap();
+        assert_eq!(value.tail.len(), 4);
+        value.tail[3] = 0;
+        assert_eq!(value.as_bytes(), &[1, 0, 0, 0, 2, 0, 3, 0, 4, 0, 0, 0]);
+
+        assert_eq!(
+            DynStruct::<u32, u16>::from_bytes(&bytes.0[..5]).unwrap_err(),
+            DynStructError::SizeMismatch { size: 5 }
+        );
+        assert_eq!(
+            DynStruct::<u32, u16>::from_bytes(&bytes.0[2..]).unwrap_err(),
+            DynStructError::Misaligned { align: 4 }
+        );
+
+        // the padded size of a `DynStruct<u64, u8>` is always a multiple of 8
+        assert!(DynStruct::<u64, u8>::from_bytes(&Aligned([0; 11]).0).is_err());
+        let value = DynStruct::<u64, u8>::from_bytes(&Aligned([7; 16]).0).unwrap();
+        assert_eq!(&value.tail, &[7; 8]);
+
+        let zero = DynStruct::<[u8; 2], ()>::from_bytes(&[1, 2]).unwrap();
+        assert_eq!(zero.header, [1, 2]);
+        assert!(zero.tail.is_empty());
+    }
+}
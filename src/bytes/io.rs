@@ -0,0 +1,145 @@
+//! Reading and writing `DynStruct`s of plain old data through `std::io`.
+
+use super::Pod;
+use crate::{BoxWriter, DynStruct, DynStructError, GlobalBox};
+use alloc::boxed::Box;
+use core::convert::TryFrom;
+use core::mem::{size_of, MaybeUninit};
+use std::io::{self, Read, Write};
+
+/// The maximum length of the tail accepted by [`DynStruct::read_from`].
+pub const DEFAULT_MAX_LEN: usize = 1 << 20;
+
+/// Reading and writing `DynStruct`s in a simple binary format: the length of the tail as a
+/// little-endian `u64`, followed by the bytes of the header and the bytes of every element of the
+/// tail, without any padding in between.
+///
+/// The header and tail are written exactly as they are laid out in memory on a little-endian
+/// target, the only kind of target these methods are available on. Plain integers and floats thus
+/// end up little-endian, while types with an explicit byte order (such as `zerocopy::byteorder`'s
+/// `BE<u32>`) are written in that order.
+impl<Header: Pod, Tail: Pod> DynStruct<Header, Tail> {
+    /// Write the `DynStruct` to the writer.
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        unsafe { self.write_to_unchecked(writer) }
+    }
+
+    /// Read a `DynStruct` written by [`DynStruct::write_to`], failing if the tail is longer than
+    /// [`DEFAULT_MAX_LEN`].
+    pub fn read_from<R: Read>(reader: R) -> io::Result<Box<Self>> {
+        Self::read_from_with_max_len(reader, DEFAULT_MAX_LEN)
+    }
+
+    /// Read a `DynStruct` written by [`DynStruct::write_to`]. The value is allocated once the
+    /// header has been read, and the tail is read directly into the allocation.
+    ///
+    /// Since the length comes from the input, it is limited to `max_len` elements: longer tails
+    /// are rejected with [`io::ErrorKind::InvalidData`] before anything is allocated.
+    pub fn read_from_with_max_len<R: Read>(reader: R, max_len: usize) -> io::Result<Box<Self>> {
+        unsafe { Self::read_from_unchecked(reader, max_len) }
+    }
+}
+
+#[doc(hidden)]
+impl<Header, Tail> DynStruct<Header, Tail> {
+    /// Implementation of [`DynStruct::write_to`], also used by the `DynStruct` derive.
+    ///
+    /// # Safety
+    ///
+    /// The header and tail may not contain any padding.
+    pub unsafe fn write_to_unchecked<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let header = core::slice::from_raw_parts(
+            (&self.header as *const Header).cast::<u8>(),
+            size_of::<Header>(),
+        );
+        let tail = core::slice::from_raw_parts(
+            self.tail.as_ptr().cast::<u8>(),
+            core::mem::size_of_val(&self.tail),
+        );
+
+        writer.write_all(&(self.tail.len() as u64).to_le_bytes())?;
+        writer.write_all(header)?;
+        writer.write_all(tail)
+    }
+
+    /// Implementation of [`DynStruct::read_from_with_max_len`], also used by the `DynStruct`
+    /// derive.
+    ///
+    /// # Safety
+    ///
+    /// Any bit pattern has to be a valid header and tail.
+    pub unsafe fn read_from_unchecked<R: Read>(
+        mut reader: R,
+        max_len: usize,
+    ) -> io::Result<Box<Self>> {
+        let mut prefix = [0; 8];
+        reader.read_exact(&mut prefix)?;
+        let len = u64::from_le_bytes(prefix);
+        let len = match usize::try_from(len) {
+            Ok(len) if len <= max_len => len,
+            _ => return Err(io_error(DynStructError::LengthLimit { len, max: max_len })),
+        };
+
+        let mut header = MaybeUninit::<Header>::zeroed();
+        reader.read_exact(core::slice::from_raw_parts_mut(
+            header.as_mut_ptr().cast::<u8>(),
+            size_of::<Header>(),
+        ))?;
+
+        // the tail is zeroed so that it can be handed to the reader as initialized bytes
+        let allocation = GlobalBox::allocate::<Header, Tail>(len, true).map_err(io_error)?;
+        let mut writer = BoxWriter::with_allocation(header.assume_init(), len, allocation);
+        reader.read_exact(core::slice::from_raw_parts_mut(
+            writer.as_mut().tail.as_mut_ptr().cast::<u8>(),
+            len * size_of::<Tail>(),
+        ))?;
+
+        writer.written = len;
+        writer.finish().map_err(io_error)
+    }
+}
+
+fn io_error(error: DynStructError) -> io::Error {
+    let kind = match error {
+        DynStructError::AllocError(_) => io::ErrorKind::OutOfMemory,
+        _ => io::ErrorKind::InvalidData,
+    };
+    io::Error::new(kind, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let value = DynStruct::new([1u8, 2, 3], vec![4u32, 5]);
+
+        let mut bytes = Vec::new();
+        value.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 8 + 3 + 2 * 4);
+        assert_eq!(bytes[..11], [2, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]);
+
+        let back = DynStruct::<[u8; 3], u32>::read_from(&bytes[..]).unwrap();
+        assert_eq!(back, value);
+
+        let error = DynStruct::<[u8; 3], u32>::read_from_with_max_len(&bytes[..], 1).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "length 2 exceeds the maximum of 1");
+
+        let error = DynStruct::<[u8; 3], u32>::read_from(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn hostile_length() {
+        let mut bytes = u64::MAX.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[0; 16]);
+
+        let error = DynStruct::<u8, u64>::read_from(&bytes[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error =
+            DynStruct::<u8, u64>::read_from_with_max_len(&bytes[..], usize::MAX).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
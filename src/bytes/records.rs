@@ -0,0 +1,340 @@
+//! Buffers of back-to-back, length-prefixed `DynStruct` records.
+//!
+//! Each record starts with the length of the tail as a little-endian `u64`. The `DynStruct` itself
+//! follows at the next multiple of its alignment (but at least 8 bytes after the start of the
+//! record), laid out exactly as in memory. The record is then padded with zeros up to the next
+//! multiple of 8 bytes, or of the alignment of the `DynStruct` if that is larger, where the next
+//! record starts.
+//!
+//! All offsets are relative to the start of the buffer, so the buffer has to be aligned for the
+//! `DynStruct` when reading records from it.
+
+use super::Pod;
+use crate::{dyn_struct_ptr, DynStruct, DynStructError};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::mem::size_of;
+
+/// An iterator over the records in a buffer, yielding a view of each `DynStruct` without copying.
+///
+/// Once a record is truncated or misaligned, the iterator yields the error and then stops.
+pub struct DynStructRecords<'a, Header, Tail> {
+    bytes: &'a [u8],
+    _marker: PhantomData<&'a DynStruct<Header, Tail>>,
+}
+
+/// Same as [`DynStructRecords`], but yields mutable views.
+pub struct DynStructRecordsMut<'a, Header, Tail> {
+    bytes: &'a mut [u8],
+    _marker: PhantomData<&'a mut DynStruct<Header, Tail>>,
+}
+
+/// Appends records to a `Vec<u8>`, in the format read by [`DynStructRecords`].
+pub struct DynStructRecordWriter<'a, Header, Tail> {
+    bytes: &'a mut Vec<u8>,
+    _marker: PhantomData<fn(&DynStruct<Header, Tail>)>,
+}
+
+/// The position of a record at the start of a buffer.
+struct Record {
+    /// The length of the tail.
+    len: usize,
+    /// The offset of the `DynStruct` from the start of the record.
+    start: usize,
+    /// The offset just past the `DynStruct`.
+    end: usize,
+    /// The offset of the next record (which may be past the end of the buffer, if the padding at
+    /// the end of the last record is missing).
+    next: usize,
+}
+
+impl<Header, Tail> DynStruct<Header, Tail> {
+    /// The alignment of every record, and the offset of the `DynStruct` within the record.
+    fn record_align() -> usize {
+        usize::max(Self::align(), size_of::<u64>())
+    }
+
+    /// Locate the record at the start of the bytes, returning an error if it is truncated.
+    fn record(bytes: &[u8]) -> Result<Record, DynStructError> {
+        let prefix = bytes
+            .get(..size_of::<u64>())
+            .ok_or(DynStructError::SliceTooShort {
+                len: bytes.len(),
+                required: size_of::<u64>(),
+            })?;
+        let len = u64::from_le_bytes(<[u8; 8]>::try_from(prefix).unwrap());
+        let len = usize::try_from(len).map_err(|_| DynStructError::SizeOverflow)?;
+
+        let align = Self::record_align();
+        let start = align;
+        let end = start
+            .checked_add(Self::layout(len)?.size())
+            .ok_or(DynStructError::SizeOverflow)?;
+        if bytes.len() < end {
+            return Err(DynStructError::SliceTooShort {
+                len: bytes.len(),
+                required: end,
+            });
+        }
+
+        let next = end
+            .checked_add(align - 1)
+            .ok_or(DynStructError::SizeOverflow)?
+            & !(align - 1);
+        Ok(Record {
+            len,
+            start,
+            end,
+            next,
+        })
+    }
+
+    fn check_record_alignment(ptr: *const u8) -> Result<(), DynStructError> {
+        let align = Self::align();
+        if (ptr as usize).is_multiple_of(align) {
+            Ok(())
+        } else {
+            Err(DynStructError::Misaligned { align })
+        }
+    }
+}
+
+impl<'a, Header: Pod, Tail: Pod> DynStructRecords<'a, Header, Tail> {
+    /// Iterate over the records in the buffer.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        DynStructRecords {
+            bytes,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The bytes of the records which have not been yielded yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl<'a, Header: Pod, Tail: Pod> Iterator for DynStructRecords<'a, Header, Tail> {
+    type Item = Result<&'a DynStruct<Header, Tail>, DynStructError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+
+        let record = DynStruct::<Header, Tail>::record(self.bytes).and_then(|record| {
+            let data = self.bytes[record.start..record.end].as_ptr();
+            DynStruct::<Header, Tail>::check_record_alignment(data)?;
+            Ok((record, data))
+        });
+
+        match record {
+            Ok((record, data)) => {
+                self.bytes = &self.bytes[record.next.min(self.bytes.len())..];
+                // SAFETY: the bytes are aligned and cover a `DynStruct` of this length, and any
+                // bit pattern is a valid `DynStruct` since both the header and tail are `Pod`
+                Some(Ok(unsafe { &*dyn_struct_ptr(data as *mut u8, record.len) }))
+            }
+            Err(error) => {
+                self.bytes = &[];
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+impl<Header: Pod, Tail: Pod> FusedIterator for DynStructRecords<'_, Header, Tail> {}
+
+impl<'a, Header: Pod, Tail: Pod> DynStructRecordsMut<'a, Header, Tail> {
+    /// Iterate over the records in the buffer.
+    ///
+    /// Fails to compile if the `DynStruct` may contain padding (see [`DynStruct::as_bytes`]).
+    pub fn new(bytes: &'a mut [u8]) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = DynStruct::<Header, Tail>::NO_PADDING;
+        DynStructRecordsMut {
+            bytes,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, Header: Pod, Tail: Pod> Iterator for DynStructRecordsMut<'a, Header, Tail> {
+    type Item = Result<&'a mut DynStruct<Header, Tail>, DynStructError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+
+        let bytes = core::mem::take(&mut self.bytes);
+        let record = DynStruct::<Header, Tail>::record(bytes).and_then(|record| {
+            let data = bytes[record.start..record.end].as_ptr();
+            DynStruct::<Header, Tail>::check_record_alignment(data)?;
+            Ok(record)
+        });
+
+        // on errors, the remaining bytes stay empty
+        let record = match record {
+            Ok(record) => record,
+            Err(error) => return Some(Err(error)),
+        };
+
+        let (current, rest) = bytes.split_at_mut(record.next.min(bytes.len()));
+        self.bytes = rest;
+        let data = current[record.start..record.end].as_mut_ptr();
+        // SAFETY: same as for `DynStructRecords`, and the `DynStruct` has no padding which writes
+        // could leave uninitialized
+        Some(Ok(unsafe { &mut *dyn_struct_ptr(data, record.len) }))
+    }
+}
+
+impl<Header: Pod, Tail: Pod> FusedIterator for DynStructRecordsMut<'_, Header, Tail> {}
+
+impl<'a, Header: Pod, Tail: Pod> DynStructRecordWriter<'a, Header, Tail> {
+    /// Append records to the end of the buffer. If the buffer already contains records, they must
+    /// have the same type.
+    pub fn new(bytes: &'a mut Vec<u8>) -> Self {
+        DynStructRecordWriter {
+            bytes,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Append a record containing a copy of the `DynStruct`.
+    pub fn push(&mut self, value: &DynStruct<Header, Tail>) {
+        self.push_parts(value.header, &value.tail)
+    }
+
+    /// Append a record with the given header and tail.
+    pub fn push_parts(&mut self, header: Header, tail: &[Tail]) {
+        let align = DynStruct::<Header, Tail>::record_align();
+        let size = DynStruct::<Header, Tail>::layout(tail.len())
+            .map(|layout| (align + layout.size() + align - 1) & !(align - 1))
+            .unwrap_or_else(|error| error.raise::<()>());
+
+        // continue after the padding of the previous record
+        let offset = (self.bytes.len() + align - 1) & !(align - 1);
+        self.bytes.resize(offset + size, 0);
+        let record = &mut self.bytes[offset..];
+
+        record[..size_of::<u64>()].copy_from_slice(&(tail.len() as u64).to_le_bytes());
+        unsafe {
+            // SAFETY: neither the header nor the tail contain padding, so all of their bytes are
+            // initialized
+            let data = record[align..].as_mut_ptr();
+            data.cast::<Header>().write_unaligned(header);
+            data.add(DynStruct::<Header, Tail>::tail_offset())
+                .copy_from_nonoverlapping(tail.as_ptr().cast::<u8>(), core::mem::size_of_val(tail));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Copy the bytes into a buffer aligned to 8 bytes.
+    fn aligned(bytes: &[u8]) -> Vec<u64> {
+        let mut buffer = vec![0u64; bytes.len().div_ceil(8)];
+        unsafe {
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer.as_mut_ptr().cast(), bytes.len())
+        };
+        buffer
+    }
+
+    fn as_bytes(buffer: &mut [u64]) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(buffer.as_mut_ptr().cast(), buffer.len() * 8) }
+    }
+
+    #[test]
+    fn records() {
+        let mut bytes = Vec::new();
+        let mut writer = DynStructRecordWriter::new(&mut bytes);
+        writer.push(&DynStruct::new(1u16, vec![10u32, 11, 12]));
+        writer.push_parts(2, &[20]);
+        writer.push_parts(3, &[]);
+        // prefix, header, padding, tail and padding
+        assert_eq!(
+            bytes.len(),
+            (8 + 2 + 2 + 12) + (8 + 2 + 2 + 4) + (8 + 2 + 6)
+        );
+        assert_eq!(bytes[..8], 3u64.to_le_bytes());
+
+        let mut buffer = aligned(&bytes);
+        let buffer = as_bytes(&mut buffer);
+        let records = DynStructRecords::<u16, u32>::new(buffer)
+            .map(|record| record.map(|value| (value.header, value.tail.to_vec())))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records, [(1, vec![10, 11, 12]), (2, vec![20]), (3, vec![])]);
+
+        // the padding at the end of the last record is optional
+        let mut records = DynStructRecords::<u16, u32>::new(&buffer[..bytes.len() - 4]);
+        assert_eq!(records.nth(2).unwrap().unwrap().header, 3);
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn invalid_records() {
+        let mut bytes = Vec::new();
+        let mut writer = DynStructRecordWriter::new(&mut bytes);
+        writer.push_parts(1u32, &[2u32, 3]);
+        writer.push_parts(4, &[5, 6]);
+        let mut buffer = aligned(&bytes);
+        let buffer = as_bytes(&mut buffer);
+
+        let mut records = DynStructRecords::<u32, u32>::new(&buffer[..bytes.len() - 5]);
+        assert_eq!(records.next().unwrap().unwrap().tail, [2, 3]);
+        assert_eq!(
+            records.next().unwrap().unwrap_err(),
+            DynStructError::SliceTooShort {
+                len: 19,
+                required: 20
+            }
+        );
+        assert!(records.next().is_none());
+
+        let mut misaligned = aligned(&[&[0][..], &bytes].concat());
+        let misaligned = &as_bytes(&mut misaligned)[1..];
+        let mut records = DynStructRecords::<u32, u32>::new(misaligned);
+        assert_eq!(
+            records.next().unwrap().unwrap_err(),
+            DynStructError::Misaligned { align: 4 }
+        );
+        assert!(records.next().is_none());
+
+        let mut too_long = u64::MAX.to_le_bytes().to_vec();
+        too_long.resize(64, 0);
+        let mut buffer = aligned(&too_long);
+        let mut records = DynStructRecords::<u32, u32>::new(as_bytes(&mut buffer));
+        assert_eq!(
+            records.next().unwrap().unwrap_err(),
+            DynStructError::SizeOverflow
+        );
+    }
+
+    #[test]
+    fn records_mut() {
+        let mut bytes = Vec::new();
+        let mut writer = DynStructRecordWriter::new(&mut bytes);
+        writer.push_parts(1u32, &[2u32, 3]);
+        writer.push_parts(4, &[5]);
+        let mut buffer = aligned(&bytes);
+        let buffer = as_bytes(&mut buffer);
+
+        for record in DynStructRecordsMut::<u32, u32>::new(buffer) {
+            let record = record.unwrap();
+            record.header *= 10;
+            record.tail.reverse();
+        }
+
+        let records = DynStructRecords::<u32, u32>::new(buffer)
+            .map(|record| record.map(|value| (value.header, value.tail.to_vec())))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records, [(10, vec![3, 2]), (40, vec![5])]);
+    }
+}
@@ -0,0 +1,100 @@
+use alloc::alloc::Layout;
+use core::fmt;
+
+/// Errors that may occur when constructing a `DynStruct` with one of the fallible constructors,
+/// such as [`DynStruct::try_new`](crate::DynStruct::try_new).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DynStructError {
+    /// The iterator yielded a different number of items than it reported through
+    /// [`ExactSizeIterator::len`]. If the iterator yielded too many items, `actual` is
+    /// `expected + 1`, since we stop consuming the iterator at that point.
+    LengthMismatch { expected: usize, actual: usize },
+    /// The total size of the `DynStruct` would exceed `isize::MAX` bytes.
+    SizeOverflow,
+    /// The allocator could not provide memory for the given layout.
+    AllocError(Layout),
+    /// The slice is too short to be viewed as a `DynStruct`.
+    SliceTooShort { len: usize, required: usize },
+    /// The length of the tail does not fit in the length type of a
+    /// [`ThinBox`](crate::ThinBox), which can store at most `max` elements.
+    LengthOverflow { len: usize, max: usize },
+    /// The bytes are not aligned to the `align` bytes required by the `DynStruct`.
+    Misaligned { align: usize },
+    /// The number of bytes is not the size of a `DynStruct` with any length of the tail.
+    SizeMismatch { size: usize },
+    /// The length prefix read from a stream announces a tail of `len` elements, more than the
+    /// `max` elements allowed.
+    LengthLimit { len: u64, max: usize },
+}
+
+impl DynStructError {
+    /// Report the error the same way the infallible constructors always have: by panicking, or by
+    /// calling [`handle_alloc_error`](alloc::alloc::handle_alloc_error) if we ran out of memory.
+    /// `I` is the type of the iterator that produced the tail.
+    #[cold]
+    #[track_caller]
+    pub(crate) fn raise<I>(self) -> ! {
+        match self {
+            DynStructError::LengthMismatch { expected, actual } if actual > expected => panic!(
+                "got more items than expected. Probable bug in `ExactSizeIterator` for `{}`?",
+                core::any::type_name::<I>(),
+            ),
+            DynStructError::LengthMismatch { .. } => panic!(
+                "got fewer items than expected. Probable bug in `ExactSizeIterator` for `{}`?",
+                core::any::type_name::<I>(),
+            ),
+            DynStructError::AllocError(layout) => alloc::alloc::handle_alloc_error(layout),
+            error => panic!("{}", error),
+        }
+    }
+}
+
+impl fmt::Display for DynStructError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DynStructError::LengthMismatch { expected, actual } if actual > expected => write!(
+                f,
+                "iterator yielded more than the {} items it reported",
+                expected
+            ),
+            DynStructError::LengthMismatch { expected, actual } => write!(
+                f,
+                "iterator yielded {} items, but reported {}",
+                actual, expected
+            ),
+            DynStructError::SizeOverflow => write!(f, "size of `DynStruct` overflows `isize::MAX`"),
+            DynStructError::AllocError(layout) => write!(
+                f,
+                "failed to allocate {} bytes with alignment {}",
+                layout.size(),
+                layout.align()
+            ),
+            DynStructError::SliceTooShort { len, required } => write!(
+                f,
+                "slice of length {} is too short (needs at least {} elements)",
+                len, required
+            ),
+            DynStructError::LengthOverflow { len, max } => write!(
+                f,
+                "length {} does not fit in the length prefix (at most {})",
+                len, max
+            ),
+            DynStructError::Misaligned { align } => {
+                write!(f, "bytes are not aligned to {} bytes", align)
+            }
+            DynStructError::SizeMismatch { size } => {
+                write!(
+                    f,
+                    "{} bytes is not the size of any `DynStruct` of this type",
+                    size
+                )
+            }
+            DynStructError::LengthLimit { len, max } => {
+                write!(f, "length {} exceeds the maximum of {}", len, max)
+            }
+        }
+    }
+}
+
+impl core::error::Error for DynStructError {}
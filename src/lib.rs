@@ -81,14 +81,158 @@
 //! ```
 //!
 //! Due to the nature of dynamically sized types, the resulting value has to be
-//! built on the heap. For safety reasons we currently only allow returning `Box`,
-//! though in a future version we may also allow `Rc` and `Arc`. In the meantime it
-//! is posible to use `Arc::from(MyDynamicType::new(...))`.
+//! built on the heap. Besides `new`, the macro also generates `new_rc` and `new_arc`
+//! which build the value directly inside an `Rc` or `Arc`, without first allocating
+//! a `Box`.
+//!
+//! If the length of the tail is not known up front, `from_iter` accepts any
+//! `IntoIterator`, growing the allocation as needed and shrinking it to fit at the
+//! end.
+//!
+//! For tails that are parsed or otherwise fallible, `try_from_iter` accepts an
+//! iterator of `Result`s and returns the first error it encounters, just like
+//! `DynStruct::try_from_iter`. It is not called `try_new`, since
+//! `DynStruct::try_new` instead reports a tail of the wrong length or a failed
+//! allocation.
+//!
+//! With the `allocator-api2` feature (or `allocator_api` on nightly), adding
+//! `#[dyn_struct(new_in)]` to the struct also generates a `new_in` constructor, which
+//! takes an additional `Allocator` and returns a `dyn_struct::allocator::Box<Self, A>`.
+//!
+//! Similarly, `#[dyn_struct(clone)]` generates a `clone_box` method and implements
+//! `Clone` for `Box<Self>`, provided all fields are `Clone`.
+//!
+//! `new_thin_arc` builds the value inside a `ThinArc`, which keeps the reference
+//! count and the length of the tail in the allocation itself so that the pointer is
+//! a single word. It derefs to a `DynStruct`, which `Foo::from_dyn_struct` (from the
+//! `DynStructLike` trait) turns back into a `&Foo`. Since the macro builds values
+//! through `DynStruct<Header, Tail>`, the sized fields must not end in padding that
+//! reaches past the alignment of the tail; this is checked at compile time.
+//!
+//! To take a value apart again, `into_parts` consumes the `Box` and returns all
+//! fields as a tuple, with the tail moved into a `Vec`.
+//!
+//! Many values of one type can share a single buffer in a `DynStructVec`, which
+//! packs them back to back and keeps only an offset and length per element, instead
+//! of an allocation and a fat pointer each.
+//!
+//! For short-lived values that are all freed together, `DynArena` places them one
+//! after the other in large blocks instead of going through the global allocator,
+//! and runs their destructors when the arena is dropped. The macro generates
+//! `Foo::new_in_arena(&arena, ..)` for this, which returns a `&mut Foo` borrowed
+//! from the arena.
+//!
+//! `#[derive(HomogeneousHeader)]` marks a `#[repr(C)]` struct whose fields are all of
+//! one type `T` (or arrays of `T`) as layout-compatible with a slice of `T`, so that
+//! `DynStruct::<MyHeader, T>::slice_view` can view a `&[T]` without copying.
+//!
+//! With the `bytemuck` (or `zerocopy`) feature, `#[dyn_struct(bytes)]` generates
+//! `from_bytes`, `from_bytes_mut` and `as_bytes`, which reinterpret a byte slice as
+//! the struct without copying, provided every field is plain old data. If both
+//! features are enabled, every field has to implement the traits of both crates.
+//!
+//! With `std` enabled as well, `DynStruct::write_to` and `DynStruct::read_from` send
+//! such values over any `Write` and `Read`: a little-endian `u64` length followed by
+//! the header and tail as laid out in memory on a little-endian target, the only
+//! targets these methods exist on. Reading allocates once and rejects lengths above
+//! a configurable maximum, and `#[dyn_struct(bytes)]` generates the same methods.
+//!
+//! To keep many such values in one buffer, `DynStructRecordWriter` appends them to
+//! a `Vec<u8>` as length-prefixed records, and `DynStructRecords` (or
+//! `DynStructRecordsMut`) iterates over them without copying, reporting truncated
+//! or misaligned records as errors.
+//!
+//! With the `serde` feature, `DynStruct` implements `Serialize` and
+//! `Box<DynStruct>` implements `Deserialize`, streaming the tail straight into the
+//! allocation. Add `#[dyn_struct(serde)]` to a struct with named fields to have it
+//! (de)serialized like a regular struct with the same fields.
+//!
+//! With the `rkyv` feature, `Box<DynStruct<H, T>>` can be archived with `rkyv`
+//! as an `ArchivedDynStruct<H::Archived, T::Archived>`, which is accessed in place
+//! and deserializes back into a `Box` with a single allocation. Add
+//! `#[dyn_struct(rkyv)]` to do the same for a derived struct.
+//!
+//! The crate supports `#![no_std]` targets with a global allocator: disable the
+//! default `std` feature (keeping `derive` if needed) and everything, including the
+//! code generated by the macro, only depends on `core` and `alloc`.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+extern crate alloc;
 
 #[cfg(feature = "derive")]
-pub use dyn_struct_derive::DynStruct;
+pub use dyn_struct_derive::{DynStruct, HomogeneousHeader};
+
+#[cfg(any(feature = "allocator_api", feature = "allocator-api2"))]
+pub mod allocator;
+mod arena;
+#[cfg(any(feature = "bytemuck", feature = "zerocopy"))]
+pub mod bytes;
+mod error;
+#[cfg(feature = "rkyv")]
+mod rkyv_impl;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod thin;
+pub mod vec;
+
+pub use arena::DynArena;
+pub use error::DynStructError;
+#[cfg(feature = "rkyv")]
+pub use rkyv_impl::ArchivedDynStruct;
+#[cfg(target_has_atomic = "ptr")]
+pub use thin::{ThinArc, ThinArcInner};
+pub use thin::{ThinBox, ThinDynStruct, ThinLength};
+pub use vec::DynStructVec;
 
-use std::mem::{align_of, size_of, MaybeUninit};
+/// Paths used by the code generated by the `DynStruct` derive, so that it also compiles in
+/// `#![no_std]` crates which have not declared `extern crate alloc`.
+#[doc(hidden)]
+pub mod __private {
+    #[cfg(target_has_atomic = "ptr")]
+    pub use alloc::sync::Arc;
+    pub use alloc::{boxed::Box, rc::Rc, vec::Vec};
+
+    #[cfg(feature = "serde")]
+    pub use crate::serde_impl::{deserialize, set_field, DeserializeHeader};
+    #[cfg(feature = "serde")]
+    pub use serde;
+
+    #[cfg(feature = "rkyv")]
+    pub use rkyv;
+
+    #[cfg(feature = "std")]
+    pub use std::io;
+}
+
+/// Expands to the given items only if the `std` feature is enabled, so that the code generated by
+/// the `DynStruct` derive can use `std` without checking the features itself.
+#[doc(hidden)]
+#[cfg(feature = "std")]
+#[macro_export]
+macro_rules! __if_std {
+    ($($item:tt)*) => { $($item)* };
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "std"))]
+#[macro_export]
+macro_rules! __if_std {
+    ($($item:tt)*) => {};
+}
+
+use alloc::alloc::{alloc, alloc_zeroed, dealloc, realloc, Layout, LayoutError};
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use core::iter::FromIterator;
+use core::mem::{align_of, size_of, MaybeUninit};
+use core::ptr::NonNull;
 
 #[repr(C)]
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -105,15 +249,46 @@ impl<Header, Tail> DynStruct<Header, Tail> {
         I: IntoIterator<Item = Tail>,
         I::IntoIter: ExactSizeIterator,
     {
-        let tail = tail.into_iter();
+        Self::write_iter::<GlobalBox, I>(header, tail, ())
+            .unwrap_or_else(|error| error.raise::<I::IntoIter>())
+    }
 
-        let mut writer = BoxWriter::<Header, Tail>::new(header, tail.len());
+    /// Same as [`DynStruct::new`], but returns an error instead of panicking if the iterator
+    /// yields a different number of items than it reports, and instead of aborting if the
+    /// allocation fails or its size overflows.
+    #[inline]
+    pub fn try_new<I>(header: Header, tail: I) -> Result<Box<Self>, DynStructError>
+    where
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        Self::write_iter::<GlobalBox, I>(header, tail, ())
+    }
 
-        for value in tail {
-            writer.write_tail::<I::IntoIter>(value);
-        }
+    /// Allocate a new `DynStruct` behind an [`Rc`]. The header and tail are written directly into
+    /// the reference-counted allocation, so this avoids the extra allocation and copy of
+    /// `Rc::from(DynStruct::new(..))`.
+    #[inline]
+    pub fn new_rc<I>(header: Header, tail: I) -> Rc<Self>
+    where
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        Self::write_iter::<RcAlloc<Header, Tail>, I>(header, tail, ())
+            .unwrap_or_else(|error| error.raise::<I::IntoIter>())
+    }
 
-        writer.finish::<I::IntoIter>()
+    /// Allocate a new `DynStruct` behind an [`Arc`]. Same as [`DynStruct::new_rc`], but atomically
+    /// reference counted.
+    #[cfg(target_has_atomic = "ptr")]
+    #[inline]
+    pub fn new_arc<I>(header: Header, tail: I) -> Arc<Self>
+    where
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        Self::write_iter::<ArcAlloc<Header, Tail>, I>(header, tail, ())
+            .unwrap_or_else(|error| error.raise::<I::IntoIter>())
     }
 
     /// Allocate a new `DynStruct` on the heap. Uses a slice instead of an iterator (as
@@ -123,131 +298,518 @@ impl<Header, Tail> DynStruct<Header, Tail> {
     where
         Tail: Copy,
     {
-        let mut writer = BoxWriter::<Header, Tail>::new(header, tail.len());
+        Self::write_slice::<GlobalBox>(header, tail, ()).unwrap_or_else(|error| error.raise::<()>())
+    }
+
+    /// Same as [`DynStruct::from_slice`], but returns an error instead of aborting if the
+    /// allocation fails or its size overflows.
+    pub fn try_from_slice(header: Header, tail: &[Tail]) -> Result<Box<Self>, DynStructError>
+    where
+        Tail: Copy,
+    {
+        Self::write_slice::<GlobalBox>(header, tail, ())
+    }
+
+    /// Same as [`DynStruct::from_slice`], but returns an [`Rc`].
+    pub fn from_slice_rc(header: Header, tail: &[Tail]) -> Rc<Self>
+    where
+        Tail: Copy,
+    {
+        Self::write_slice::<RcAlloc<Header, Tail>>(header, tail, ())
+            .unwrap_or_else(|error| error.raise::<()>())
+    }
+
+    /// Same as [`DynStruct::from_slice`], but returns an [`Arc`].
+    #[cfg(target_has_atomic = "ptr")]
+    pub fn from_slice_arc(header: Header, tail: &[Tail]) -> Arc<Self>
+    where
+        Tail: Copy,
+    {
+        Self::write_slice::<ArcAlloc<Header, Tail>>(header, tail, ())
+            .unwrap_or_else(|error| error.raise::<()>())
+    }
+
+    /// Same as [`DynStruct::from_slice`], but clones the elements of the tail, so it does not
+    /// require `Tail: Copy`.
+    pub fn from_slice_cloned(header: Header, tail: &[Tail]) -> Box<Self>
+    where
+        Tail: Clone,
+    {
+        Self::new(header, tail.iter().cloned())
+    }
+
+    /// Allocate a new `DynStruct` on the heap with a tail of `len` elements, each given by calling
+    /// `f` with its index.
+    pub fn from_fn<F>(header: Header, len: usize, mut f: F) -> Box<Self>
+    where
+        F: FnMut(usize) -> Tail,
+    {
+        Self::from_fn_with_header(header, len, |_, index| f(index))
+    }
+
+    /// Same as [`DynStruct::from_fn`], but `f` also receives a reference to the header, which has
+    /// already been moved into the allocation, so the tail can be derived from it.
+    pub fn from_fn_with_header<F>(header: Header, len: usize, mut f: F) -> Box<Self>
+    where
+        F: FnMut(&Header, usize) -> Tail,
+    {
+        let mut writer = BoxWriter::<Header, Tail>::new(header, len, ())
+            .unwrap_or_else(|error| error.raise::<F>());
+
+        for index in 0..len {
+            let value = f(writer.header(), index);
+            writer
+                .write_tail(value)
+                .unwrap_or_else(|error| error.raise::<F>());
+        }
+
+        writer.finish().unwrap_or_else(|error| error.raise::<F>())
+    }
+
+    /// Allocate a new `DynStruct` on the heap with a tail of `len` clones of `value`.
+    pub fn repeat(header: Header, value: Tail, len: usize) -> Box<Self>
+    where
+        Tail: Clone,
+    {
+        Self::new(header, core::iter::repeat_n(value, len))
+    }
+
+    /// Allocate a new `DynStruct` on the heap with an uninitialized tail of `len` elements. Once
+    /// the tail has been written, call [`DynStruct::assume_init`] to get the initialized value.
+    pub fn new_uninit(header: Header, len: usize) -> Box<DynStruct<Header, MaybeUninit<Tail>>> {
+        let alloc = GlobalBox::allocate::<Header, Tail>(len, false)
+            .unwrap_or_else(|error| error.raise::<()>());
+        Self::uninit_from_allocation(header, len, alloc)
+    }
+
+    /// Same as [`DynStruct::new_uninit`], but the tail is filled with zero bytes. Depending on the
+    /// allocator, this may be a lot cheaper than writing the zeroes ourselves.
+    pub fn new_zeroed(header: Header, len: usize) -> Box<DynStruct<Header, MaybeUninit<Tail>>> {
+        let alloc = GlobalBox::allocate::<Header, Tail>(len, true)
+            .unwrap_or_else(|error| error.raise::<()>());
+        Self::uninit_from_allocation(header, len, alloc)
+    }
+
+    fn uninit_from_allocation(
+        header: Header,
+        len: usize,
+        alloc: GlobalBox,
+    ) -> Box<DynStruct<Header, MaybeUninit<Tail>>> {
+        let mut writer =
+            BoxWriter::<Header, MaybeUninit<Tail>>::with_allocation(header, len, alloc);
+        // `MaybeUninit` does not need to be initialized
+        writer.written = len;
+        writer.finish().unwrap_or_else(|error| error.raise::<()>())
+    }
+
+    /// Allocate a new `DynStruct` on the heap from an iterator of unknown length. The allocation
+    /// starts out with room for the lower bound of the iterator's `size_hint` and grows as needed,
+    /// before being shrunk to fit, so no intermediate `Vec` is required. Prefer
+    /// [`DynStruct::new`] if the length is known up front.
+    pub fn from_iter<I>(header: Header, tail: I) -> Box<Self>
+    where
+        I: IntoIterator<Item = Tail>,
+    {
+        let tail = tail.into_iter().map(Ok::<Tail, Infallible>);
+        match Self::write_unsized_iter(header, tail) {
+            Ok(Ok(value)) => value,
+            Ok(Err(never)) => match never {},
+            Err(error) => error.raise::<I::IntoIter>(),
+        }
+    }
+
+    /// Allocate a new `DynStruct` on the heap from an iterator of `Result`s, such as when parsing
+    /// the tail elements. Stops at the first `Err`, in which case the elements written so far are
+    /// dropped, the allocation is freed and the error is returned. Otherwise this behaves like
+    /// [`DynStruct::from_iter`].
+    pub fn try_from_iter<I, E>(header: Header, tail: I) -> Result<Box<Self>, E>
+    where
+        I: IntoIterator<Item = Result<Tail, E>>,
+    {
+        Self::write_unsized_iter(header, tail).unwrap_or_else(|error| error.raise::<I::IntoIter>())
+    }
+
+    #[inline]
+    fn write_iter<A, I>(
+        header: Header,
+        tail: I,
+        allocator: A::Allocator,
+    ) -> Result<A::Output, DynStructError>
+    where
+        A: Allocation<Header, Tail>,
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let tail = tail.into_iter();
+
+        let mut writer = BoxWriter::<Header, Tail, A>::new(header, tail.len(), allocator)?;
+
+        for value in tail {
+            writer.write_tail(value)?;
+        }
+
+        writer.finish()
+    }
+
+    /// The outer `Result` reports failures of the allocation itself, while the inner one holds the
+    /// first error yielded by the iterator.
+    fn write_unsized_iter<I, E>(
+        header: Header,
+        tail: I,
+    ) -> Result<Result<Box<Self>, E>, DynStructError>
+    where
+        I: IntoIterator<Item = Result<Tail, E>>,
+    {
+        let tail = tail.into_iter();
+
+        let mut writer = BoxWriter::<Header, Tail>::new(header, tail.size_hint().0, ())?;
+
+        if let Err(error) = writer.extend(tail)? {
+            // the writer takes care of dropping everything written so far
+            return Ok(Err(error));
+        }
+
+        writer.shrink_to_fit()?;
+        writer.finish().map(Ok)
+    }
+
+    #[inline]
+    fn write_slice<A>(
+        header: Header,
+        tail: &[Tail],
+        allocator: A::Allocator,
+    ) -> Result<A::Output, DynStructError>
+    where
+        A: Allocation<Header, Tail>,
+        Tail: Copy,
+    {
+        let mut writer = BoxWriter::<Header, Tail, A>::new(header, tail.len(), allocator)?;
         unsafe {
             writer.write_slice(tail);
         }
-        writer.finish::<()>()
+        writer.finish()
+    }
+
+    #[inline]
+    const fn align() -> usize {
+        if align_of::<Header>() > align_of::<Tail>() {
+            align_of::<Header>()
+        } else {
+            align_of::<Tail>()
+        }
+    }
+
+    /// Returns the layout that a `DynStruct<Header, Tail>` with a tail of `len` elements is
+    /// allocated with.
+    #[inline]
+    fn layout(len: usize) -> Result<Layout, DynStructError> {
+        Self::layout_for(len).map_err(|_| DynStructError::SizeOverflow)
+    }
+}
+
+impl<Header, Tail> DynStruct<Header, Tail> {
+    /// Returns the layout of a `DynStruct<Header, Tail>` with a tail of `len` elements, or an
+    /// error if its size would exceed `isize::MAX`.
+    ///
+    /// This is the layout that every constructor allocates with, and equals what
+    /// [`Layout::for_value`] returns for the resulting value (so the size includes any padding
+    /// needed to round it up to the alignment).
+    ///
+    /// ```
+    /// # use dyn_struct::DynStruct;
+    /// # use std::alloc::Layout;
+    /// let value = DynStruct::new(1u64, [2u8, 3, 4]);
+    /// let layout = DynStruct::<u64, u8>::layout_for(3).unwrap();
+    /// assert_eq!(layout, Layout::for_value(&*value));
+    /// assert_eq!(layout.size(), 16);
+    /// ```
+    #[inline]
+    pub fn layout_for(len: usize) -> Result<Layout, LayoutError> {
+        let (layout, _) = Layout::new::<Header>().extend(Layout::array::<Tail>(len)?)?;
+        Ok(layout.pad_to_align())
+    }
+
+    /// Returns the offset in bytes from the start of a `DynStruct<Header, Tail>` to the first
+    /// element of its tail.
+    #[inline]
+    pub const fn tail_offset() -> usize {
+        size_of::<Header>().next_multiple_of(align_of::<Tail>())
+    }
+
+    /// Returns the largest length of the tail such that the size of the `DynStruct` (as given by
+    /// [`DynStruct::layout_for`]) is at most `bytes`.
+    ///
+    /// Returns `None` if not even a `DynStruct` with an empty tail fits, or if `Tail` is
+    /// zero-sized, as the length cannot be recovered from the size then.
+    ///
+    /// ```
+    /// # use dyn_struct::DynStruct;
+    /// assert_eq!(DynStruct::<u64, u8>::len_for_size(16), Some(8));
+    /// assert_eq!(DynStruct::<u64, u8>::len_for_size(15), Some(0));
+    /// assert_eq!(DynStruct::<u64, u8>::len_for_size(7), None);
+    /// ```
+    #[inline]
+    pub fn len_for_size(bytes: usize) -> Option<usize> {
+        if size_of::<Tail>() == 0 {
+            return None;
+        }
+        // the size is always a multiple of the alignment
+        let usable = bytes - bytes % Self::align();
+        let tail_bytes = usable.checked_sub(Self::tail_offset())?;
+        Some(tail_bytes / size_of::<Tail>())
+    }
+}
+
+/// Builds a fat pointer to a `DynStruct` located at `data` with a tail of `len` elements.
+#[inline]
+fn dyn_struct_ptr<Header, Tail>(data: *mut u8, len: usize) -> *mut DynStruct<Header, Tail> {
+    // use a slice as an intermediary to get a fat pointer containing the correct length of the tail
+    core::ptr::slice_from_raw_parts_mut(data.cast::<()>(), len) as *mut DynStruct<Header, Tail>
+}
+
+/// A type with the same alignment as `DynStruct<Header, Tail>`, whose size equals its alignment.
+/// A slice of these can thus hold a `DynStruct` of any length.
+#[repr(C)]
+struct Chunk<Header, Tail> {
+    _header: [Header; 0],
+    _tail: [Tail; 0],
+    _byte: u8,
+}
+
+/// Memory that a [`BoxWriter`] can initialize a `DynStruct` in. Dropping the allocation releases
+/// the memory without running any destructors.
+///
+/// # Safety
+///
+/// The pointer returned by `as_mut_ptr` must be aligned for, and valid for writes of, a
+/// `DynStruct<Header, Tail>` with the tail length passed to `new`.
+unsafe trait Allocation<Header, Tail>: Sized {
+    /// The smart pointer returned once the `DynStruct` has been initialized.
+    type Output;
+
+    /// Where the memory comes from, if not implied by the type of allocation.
+    type Allocator;
+
+    fn new(len: usize, allocator: Self::Allocator) -> Result<Self, DynStructError>;
+
+    fn as_mut_ptr(&mut self) -> *mut u8;
+
+    /// # Safety
+    ///
+    /// Assumes that the `DynStruct` in this allocation has been fully initialized with a tail of
+    /// `len` elements.
+    unsafe fn finish(self, len: usize) -> Self::Output;
+}
+
+/// Memory from the global allocator, owned by a `Box` once initialized.
+struct GlobalBox {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+unsafe impl<Header, Tail> Allocation<Header, Tail> for GlobalBox {
+    type Output = Box<DynStruct<Header, Tail>>;
+    type Allocator = ();
+
+    #[inline]
+    fn new(len: usize, _: ()) -> Result<Self, DynStructError> {
+        GlobalBox::allocate::<Header, Tail>(len, false)
     }
 
     #[inline]
-    fn align() -> usize {
-        usize::max(align_of::<Header>(), align_of::<Tail>())
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr.as_ptr()
     }
 
-    /// Returns the total size of the `DynStruct<Header, Tail>` structure, provided the length of the
-    /// tail.
     #[inline]
-    fn size(len: usize) -> usize {
-        let header = size_of::<Header>();
+    unsafe fn finish(self, len: usize) -> Self::Output {
+        let ptr = dyn_struct_ptr(self.ptr.as_ptr(), len);
+        // ownership of the memory is transferred to the `Box`
+        core::mem::forget(self);
+        Box::from_raw(ptr)
+    }
+}
+
+impl GlobalBox {
+    /// Allocate memory for a `DynStruct` with a tail of `len` elements, optionally filled with
+    /// zeroes.
+    #[inline]
+    fn allocate<Header, Tail>(len: usize, zeroed: bool) -> Result<Self, DynStructError> {
+        let layout = DynStruct::<Header, Tail>::layout(len)?;
 
-        let tail_align = align_of::<Tail>();
-        let padding = if header % tail_align == 0 {
-            0
+        let ptr = if layout.size() == 0 {
+            // We cannot allocate a region of 0 bytes, but `Box` accepts any well-aligned pointer
+            // for zero-sized values.
+            NonNull::<Chunk<Header, Tail>>::dangling().cast()
         } else {
-            tail_align - header % tail_align
+            let raw = unsafe {
+                if zeroed {
+                    alloc_zeroed(layout)
+                } else {
+                    alloc(layout)
+                }
+            };
+            NonNull::new(raw).ok_or(DynStructError::AllocError(layout))?
         };
 
-        let tail = size_of::<Tail>() * len;
+        Ok(GlobalBox { ptr, layout })
+    }
+
+    /// Move the allocation to fit a `DynStruct` with a tail of `len` elements. The contents are
+    /// preserved up to the smaller of the old and new sizes.
+    fn resize<Header, Tail>(&mut self, len: usize) -> Result<(), DynStructError> {
+        let layout = DynStruct::<Header, Tail>::layout(len)?;
 
-        header + padding + tail
+        if self.layout.size() == 0 || layout.size() == 0 {
+            // there are no bytes to preserve, so we can start over with a fresh allocation
+            *self = <GlobalBox as Allocation<Header, Tail>>::new(len, ())?;
+            return Ok(());
+        }
+
+        let raw = unsafe { realloc(self.ptr.as_ptr(), self.layout, layout.size()) };
+        self.ptr = NonNull::new(raw).ok_or(DynStructError::AllocError(layout))?;
+        self.layout = layout;
+        Ok(())
+    }
+}
+
+impl Drop for GlobalBox {
+    fn drop(&mut self) {
+        if self.layout.size() != 0 {
+            unsafe { dealloc(self.ptr.as_ptr(), self.layout) }
+        }
     }
 }
 
-struct BoxWriter<Header, Tail> {
+type RcAlloc<Header, Tail> = Rc<[MaybeUninit<Chunk<Header, Tail>>]>;
+#[cfg(target_has_atomic = "ptr")]
+type ArcAlloc<Header, Tail> = Arc<[MaybeUninit<Chunk<Header, Tail>>]>;
+
+/// Reference counted slices of `Chunk`s have the same size and alignment as the `DynStruct` they
+/// contain, so we can convert between the two with `into_raw` and `from_raw`.
+macro_rules! impl_rc_allocation {
+    ($rc:ident) => {
+        unsafe impl<Header, Tail> Allocation<Header, Tail>
+            for $rc<[MaybeUninit<Chunk<Header, Tail>>]>
+        {
+            type Output = $rc<DynStruct<Header, Tail>>;
+            type Allocator = ();
+
+            #[inline]
+            fn new(len: usize, _: ()) -> Result<Self, DynStructError> {
+                let layout = DynStruct::<Header, Tail>::layout(len)?;
+                let chunks = layout.size().div_ceil(size_of::<Chunk<Header, Tail>>());
+                Ok($rc::new_uninit_slice(chunks))
+            }
+
+            #[inline]
+            fn as_mut_ptr(&mut self) -> *mut u8 {
+                // the allocation is not shared with anyone until we call `finish`
+                $rc::get_mut(self).unwrap().as_mut_ptr().cast()
+            }
+
+            #[inline]
+            unsafe fn finish(self, len: usize) -> Self::Output {
+                let raw = $rc::into_raw(self) as *mut u8;
+                $rc::from_raw(dyn_struct_ptr(raw, len))
+            }
+        }
+    };
+}
+
+impl_rc_allocation!(Rc);
+#[cfg(target_has_atomic = "ptr")]
+impl_rc_allocation!(Arc);
+
+struct BoxWriter<Header, Tail, A = GlobalBox> {
     raw: *mut DynStruct<Header, MaybeUninit<Tail>>,
     written: usize,
+    alloc: A,
 }
 
-impl<Header, Tail> BoxWriter<Header, Tail> {
+impl<Header, Tail, A: Allocation<Header, Tail>> BoxWriter<Header, Tail, A> {
     #[inline]
-    pub fn new(header: Header, len: usize) -> Self {
-        let total_size = DynStruct::<Header, Tail>::size(len);
-        let align = DynStruct::<Header, Tail>::align();
-
-        let fat_ptr = if total_size == 0 {
-            // We cannot allocate a region of 0 bytes, thus we use `Box` to create a dangling
-            // pointer with the correct length.
-            let slice: Box<[()]> = Box::from(slice_with_len(len));
-
-            // This is a zero-size-type, so moving it into the allocation has no effect. Instead we
-            // just create a new one "out of thin air" by casting the allocation to the appropriate
-            // header. We need this call to `forget` to make sure the destructor of the original
-            // header does not run.
-            std::mem::forget(header);
-
-            Box::into_raw(slice) as *mut DynStruct<Header, MaybeUninit<Tail>>
-        } else {
-            unsafe {
-                // Allocate enough memory to store both the header and tail
-                let layout = std::alloc::Layout::from_size_align(total_size, align).unwrap();
-                let raw = std::alloc::alloc(layout);
-                if raw.is_null() {
-                    std::alloc::handle_alloc_error(layout)
-                }
+    pub fn new(
+        header: Header,
+        len: usize,
+        allocator: A::Allocator,
+    ) -> Result<Self, DynStructError> {
+        Ok(Self::with_allocation(header, len, A::new(len, allocator)?))
+    }
 
-                // Initialize the header field
-                raw.cast::<Header>().write(header);
+    /// Start writing into an existing allocation, which must have room for a tail of `len`
+    /// elements.
+    #[inline]
+    fn with_allocation(header: Header, len: usize, mut alloc: A) -> Self {
+        let raw = alloc.as_mut_ptr();
 
-                // use a slice as an intermediary to get a fat pointer containing the correct
-                // length of the tail
-                let slice = std::slice::from_raw_parts_mut(raw as *mut (), len);
-                slice as *mut [()] as *mut DynStruct<Header, MaybeUninit<Tail>>
-            }
-        };
+        unsafe {
+            // Initialize the header field
+            raw.cast::<Header>().write(header);
+        }
 
         BoxWriter {
-            raw: fat_ptr,
+            raw: dyn_struct_ptr(raw, len),
             written: 0,
+            alloc,
         }
     }
 
     #[inline]
-    fn write_tail<I>(&mut self, value: Tail) {
+    fn write_tail(&mut self, value: Tail) -> Result<(), DynStructError> {
         let written = self.written;
         let tail = &mut self.as_mut().tail;
 
-        assert!(
-            written < tail.len(),
-            "got more items than expected. Probable bug in `ExactSizeIterator` for `{}`?",
-            std::any::type_name::<I>(),
-        );
+        if written >= tail.len() {
+            return Err(DynStructError::LengthMismatch {
+                expected: tail.len(),
+                actual: written + 1,
+            });
+        }
 
         tail[written].write(value);
         self.written += 1;
+        Ok(())
     }
 
     #[inline]
-    fn finish<I>(mut self) -> Box<DynStruct<Header, Tail>> {
+    fn finish(mut self) -> Result<A::Output, DynStructError> {
         let len = self.as_mut().tail.len();
-        assert_eq!(
-            self.written,
-            len,
-            "got fewer items than expected. Probable bug in `ExactSizeIterator` for `{}`?",
-            std::any::type_name::<I>(),
-        );
+        if self.written != len {
+            return Err(DynStructError::LengthMismatch {
+                expected: len,
+                actual: self.written,
+            });
+        }
 
-        // This cast from `DynStruct<Header, MaybeUninit<Tail>>` is sound since all tail elements
-        // now have been initialized
-        let init = self.raw as *mut DynStruct<Header, Tail>;
+        unsafe {
+            let alloc = core::ptr::read(&self.alloc);
 
-        // once we have finished constructing the value, don't run the destructor
-        std::mem::forget(self);
+            // once we have finished constructing the value, don't run the destructor
+            core::mem::forget(self);
 
-        unsafe { Box::from_raw(init) }
+            // all tail elements have now been initialized
+            Ok(alloc.finish(len))
+        }
     }
 
     fn as_mut(&mut self) -> &mut DynStruct<Header, MaybeUninit<Tail>> {
         unsafe { &mut *self.raw }
     }
 
+    fn capacity(&mut self) -> usize {
+        self.as_mut().tail.len()
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &(*self.raw).header }
+    }
+
     /// # Safety
     ///
-    /// Assumes that this writer was created with a capacity for `values.len()`
-    unsafe fn write_slice(&mut self, values: &[Tail])
-    where
-        Tail: Copy,
-    {
+    /// Assumes that this writer was created with a capacity for `values.len()`. The values are
+    /// copied bitwise, so unless `Tail: Copy`, the originals must not be used afterwards.
+    unsafe fn write_slice(&mut self, values: &[Tail]) {
         self.as_mut()
             .tail
             .as_mut_ptr()
@@ -256,56 +818,541 @@ impl<Header, Tail> BoxWriter<Header, Tail> {
     }
 }
 
-impl<Header, Tail> Drop for BoxWriter<Header, Tail> {
+impl<Header, Tail> BoxWriter<Header, Tail, GlobalBox> {
+    /// Take over an existing `DynStruct`, treating all of its tail as written.
+    fn from_box(value: Box<DynStruct<Header, Tail>>) -> Self {
+        let len = value.tail.len();
+        let raw = Box::into_raw(value);
+        let alloc = GlobalBox {
+            ptr: unsafe { NonNull::new_unchecked(raw.cast::<u8>()) },
+            // the value was allocated with this layout, so it has to be valid
+            layout: DynStruct::<Header, Tail>::layout(len).unwrap(),
+        };
+
+        BoxWriter {
+            raw: raw as *mut DynStruct<Header, MaybeUninit<Tail>>,
+            written: len,
+            alloc,
+        }
+    }
+
+    /// Write every item of the iterator to the tail, growing the allocation as needed. The outer
+    /// `Result` reports failures of the allocation itself, while the inner one holds the first
+    /// error yielded by the iterator.
+    fn extend<I, E>(&mut self, tail: I) -> Result<Result<(), E>, DynStructError>
+    where
+        I: IntoIterator<Item = Result<Tail, E>>,
+    {
+        let mut tail = tail.into_iter();
+
+        while let Some(value) = tail.next() {
+            if self.written == self.capacity() {
+                // grow geometrically, so that pushing `n` items only requires `O(log n)` moves
+                let required = tail.size_hint().0.saturating_add(1);
+                let capacity = usize::max(
+                    self.written.saturating_add(required),
+                    self.capacity().saturating_mul(2),
+                );
+                self.resize(capacity)?;
+            }
+            match value {
+                Ok(value) => self.write_tail(value)?,
+                Err(error) => return Ok(Err(error)),
+            }
+        }
+
+        Ok(Ok(()))
+    }
+
+    fn shrink_to_fit(&mut self) -> Result<(), DynStructError> {
+        if self.written != self.capacity() {
+            self.resize(self.written)?;
+        }
+        Ok(())
+    }
+
+    /// Change the capacity of the tail to `len` elements. Must not be less than the number of
+    /// elements written so far.
+    fn resize(&mut self, len: usize) -> Result<(), DynStructError> {
+        debug_assert!(len >= self.written);
+        self.alloc.resize::<Header, Tail>(len)?;
+        self.raw = dyn_struct_ptr(self.alloc.ptr.as_ptr(), len);
+        Ok(())
+    }
+}
+
+impl<Header, Tail, A> Drop for BoxWriter<Header, Tail, A> {
     fn drop(&mut self) {
         unsafe {
             // SAFETY: the header field is always initialized
-            std::ptr::drop_in_place(self.raw.cast::<Header>());
+            core::ptr::drop_in_place(self.raw.cast::<Header>());
 
             let initialized = self.written;
-            let tail = &mut self.as_mut().tail;
-            for i in 0..initialized {
-                tail[i].as_mut_ptr().drop_in_place();
+            let tail = &mut (*self.raw).tail;
+            for value in &mut tail[..initialized] {
+                value.as_mut_ptr().drop_in_place();
             }
         }
+
+        // `self.alloc` releases the memory when it is dropped after this
     }
 }
 
-impl<T> DynStruct<T, T> {
-    /// Get a `DynStruct` as a view over a slice (this does not allocate).
-    pub fn slice_view(values: &[T]) -> &Self {
-        assert!(
-            !values.is_empty(),
-            "attempted to create `{}` from empty slice (needs at least 1 element)",
-            std::any::type_name::<Self>()
-        );
-        let slice = &values[..values.len() - 1];
-        unsafe { &*(slice as *const [T] as *const Self) }
+impl<Header: Default, Tail> FromIterator<Tail> for Box<DynStruct<Header, Tail>> {
+    /// Collect the tail of a `DynStruct` with a default header. See [`DynStruct::from_iter`].
+    fn from_iter<I: IntoIterator<Item = Tail>>(tail: I) -> Self {
+        DynStruct::from_iter(Header::default(), tail)
+    }
+}
+
+impl<Header, Tail> DynStruct<Header, Tail> {
+    /// Consume the `DynStruct`, moving the header and the tail out of the allocation.
+    pub fn into_parts(self: Box<Self>) -> (Header, Vec<Tail>) {
+        let len = self.tail.len();
+        let mut tail = Vec::<Tail>::with_capacity(len);
+
+        let raw = Box::into_raw(self);
+        unsafe {
+            let header = core::ptr::read(&(*raw).header);
+            tail.as_mut_ptr()
+                .copy_from_nonoverlapping((*raw).tail.as_ptr(), len);
+            tail.set_len(len);
+
+            // the contents have been moved out, so only free the memory
+            drop(Box::from_raw(
+                raw as *mut DynStruct<MaybeUninit<Header>, MaybeUninit<Tail>>,
+            ));
+
+            (header, tail)
+        }
+    }
+
+    /// Consume the `DynStruct`, returning the header and dropping the tail.
+    pub fn into_header(self: Box<Self>) -> Header {
+        let raw = Box::into_raw(self);
+        unsafe {
+            let header = core::ptr::read(&(*raw).header);
+            // drops the tail, but not the header we just moved out
+            drop(Box::from_raw(
+                raw as *mut DynStruct<MaybeUninit<Header>, Tail>,
+            ));
+            header
+        }
+    }
+
+    #[inline]
+    fn write_vec<A>(
+        header: Header,
+        mut tail: Vec<Tail>,
+        allocator: A::Allocator,
+    ) -> Result<A::Output, DynStructError>
+    where
+        A: Allocation<Header, Tail>,
+    {
+        let mut writer = BoxWriter::<Header, Tail, A>::new(header, tail.len(), allocator)?;
+        unsafe {
+            // the elements are moved into the writer, so the vector must not drop them
+            writer.write_slice(&tail);
+            tail.set_len(0);
+        }
+        writer.finish()
+    }
+}
+
+impl<Header, Tail> IntoIterator for Box<DynStruct<Header, Tail>> {
+    type Item = Tail;
+    type IntoIter = alloc::vec::IntoIter<Tail>;
+
+    /// Iterate over the elements of the tail by value. The header is dropped right away.
+    fn into_iter(self) -> Self::IntoIter {
+        DynStruct::into_parts(self).1.into_iter()
+    }
+}
+
+impl<Header, Tail> From<(Header, Vec<Tail>)> for Box<DynStruct<Header, Tail>> {
+    /// Move the header and the elements of the vector into a new `DynStruct`.
+    fn from((header, tail): (Header, Vec<Tail>)) -> Self {
+        DynStruct::write_vec::<GlobalBox>(header, tail, ())
+            .unwrap_or_else(|error| error.raise::<()>())
+    }
+}
+
+impl<Header, Tail> From<(Header, Box<[Tail]>)> for Box<DynStruct<Header, Tail>> {
+    /// Move the header and the elements of the slice into a new `DynStruct`.
+    fn from((header, tail): (Header, Box<[Tail]>)) -> Self {
+        Box::from((header, tail.into_vec()))
     }
 }
 
-impl<T, const N: usize> DynStruct<[T; N], T> {
-    /// Get a `DynStruct` as a view over a slice (this does not allocate).
+impl<Header, Tail> DynStruct<Header, MaybeUninit<Tail>> {
+    /// Convert a `DynStruct` created by [`DynStruct::new_uninit`] or [`DynStruct::new_zeroed`]
+    /// into one with an initialized tail, without copying it.
+    ///
+    /// # Safety
+    ///
+    /// Every element of the tail must have been initialized.
+    pub unsafe fn assume_init(self: Box<Self>) -> Box<DynStruct<Header, Tail>> {
+        let raw = Box::into_raw(self);
+        Box::from_raw(raw as *mut DynStruct<Header, Tail>)
+    }
+}
+
+/// Since a `DynStruct` stores its tail inline, changing the length of the tail means moving the
+/// value to a new allocation. These methods thus consume the `Box` and return the (possibly moved)
+/// value, using `realloc` so that the allocator may resize it in place.
+///
+/// A boxed `DynStruct` never has any spare capacity: its allocation always fits the tail exactly.
+/// There is thus no `shrink_to` (or `shrink_to_fit`), as there is never anything to release.
+/// `truncate` both drops the removed elements and shrinks the allocation to the new length.
+impl<Header, Tail> DynStruct<Header, Tail> {
+    /// Append a single element to the end of the tail.
+    ///
+    /// Without spare capacity, every call resizes the allocation to fit exactly one more element,
+    /// which may move the whole value. Pushing `n` elements one at a time can thus take `O(n²)`
+    /// time, so prefer [`DynStruct::extend`] for appending several elements, which grows the
+    /// allocation geometrically.
+    pub fn push(self: Box<Self>, value: Tail) -> Box<Self> {
+        Self::try_push(self, value).unwrap_or_else(|error| error.raise::<()>())
+    }
+
+    fn try_push(self: Box<Self>, value: Tail) -> Result<Box<Self>, DynStructError> {
+        let mut writer = BoxWriter::from_box(self);
+        let len = writer.written.checked_add(1);
+        writer.resize(len.ok_or(DynStructError::SizeOverflow)?)?;
+        writer.write_tail(value)?;
+        writer.finish()
+    }
+
+    /// Append every item of the iterator to the end of the tail. The allocation grows
+    /// geometrically while the iterator is consumed, and is shrunk to fit at the end.
+    pub fn extend<I>(self: Box<Self>, tail: I) -> Box<Self>
+    where
+        I: IntoIterator<Item = Tail>,
+    {
+        let mut writer = BoxWriter::from_box(self);
+        match writer.extend(tail.into_iter().map(Ok::<Tail, Infallible>)) {
+            Ok(Ok(())) => {}
+            Ok(Err(never)) => match never {},
+            Err(error) => error.raise::<I::IntoIter>(),
+        }
+        writer
+            .shrink_to_fit()
+            .and_then(|()| writer.finish())
+            .unwrap_or_else(|error| error.raise::<I::IntoIter>())
+    }
+
+    /// Shorten the tail to `len` elements, dropping the rest. Has no effect if the tail is
+    /// already shorter than that.
+    pub fn truncate(self: Box<Self>, len: usize) -> Box<Self> {
+        if len >= self.tail.len() {
+            return self;
+        }
+
+        let mut writer = BoxWriter::from_box(self);
+        let old_len = writer.written;
+
+        // if one of the destructors panic, the writer drops the remaining elements
+        writer.written = len;
+        unsafe {
+            let removed = &mut writer.as_mut().tail[len..old_len];
+            core::ptr::drop_in_place(removed as *mut [MaybeUninit<Tail>] as *mut [Tail]);
+        }
+
+        writer
+            .shrink_to_fit()
+            .and_then(|()| writer.finish())
+            .unwrap_or_else(|error| error.raise::<()>())
+    }
+}
+
+impl<Header: Clone, Tail: Clone> Clone for Box<DynStruct<Header, Tail>> {
+    /// Clone the header and every element of the tail into a new allocation. If any of the clones
+    /// panic, the elements cloned so far are dropped and the allocation is freed.
+    ///
+    /// The tail is always cloned element by element, as choosing a faster path for `Copy` types
+    /// would require specialization. To copy the tail in one go, use [`DynStruct::from_slice`].
+    fn clone(&self) -> Self {
+        DynStruct::new(self.header.clone(), self.tail.iter().cloned())
+    }
+}
+
+impl<Header: Clone, Tail: Clone> DynStruct<Header, Tail> {
+    /// Returns a mutable reference to the `DynStruct` behind an `Rc`. If there are other `Rc` or
+    /// `Weak` pointers to the same allocation, the contents are first cloned into a new one (just
+    /// like [`Rc::make_mut`], which only works for sized types).
+    pub fn make_mut_rc(this: &mut Rc<Self>) -> &mut Self {
+        if Rc::get_mut(this).is_none() {
+            *this = Self::new_rc(this.header.clone(), this.tail.iter().cloned());
+        }
+        Rc::get_mut(this).unwrap()
+    }
+
+    /// Same as [`DynStruct::make_mut_rc`], but for an [`Arc`].
+    #[cfg(target_has_atomic = "ptr")]
+    pub fn make_mut_arc(this: &mut Arc<Self>) -> &mut Self {
+        if Arc::get_mut(this).is_none() {
+            *this = Self::new_arc(this.header.clone(), this.tail.iter().cloned());
+        }
+        Arc::get_mut(this).unwrap()
+    }
+}
+
+impl<Header: Clone, Tail: Clone> ToOwned for DynStruct<Header, Tail> {
+    type Owned = Box<Self>;
+
+    /// Clone the `DynStruct` into a new `Box`, which makes `Cow<'_, DynStruct<Header, Tail>>`
+    /// work, for example on top of [`DynStruct::slice_view`].
+    fn to_owned(&self) -> Box<Self> {
+        Self::new(self.header.clone(), self.tail.iter().cloned())
+    }
+}
+
+/// A header made up of `LEN` values of type `T`, such that a `DynStruct<Self, T>` has the same
+/// layout as a slice of `T`. This allows viewing slices as `DynStruct`s, see
+/// [`DynStruct::slice_view`].
+///
+/// Implemented for `T` itself and for arrays `[T; N]`. For your own `#[repr(C)]` structs, use
+/// `#[derive(HomogeneousHeader)]`, which checks that every field is a `T` or an array of `T`, and
+/// that the struct has the size and alignment of an array of `T`:
+///
+/// ```compile_fail
+/// # use dyn_struct::{DynStruct, HomogeneousHeader};
+/// #[repr(C, align(16))]
+/// #[derive(HomogeneousHeader)]
+/// struct Overaligned {
+///     x: u32,
+///     y: u32,
+/// }
+///
+/// let view = DynStruct::<Overaligned, u32>::slice_view(&[1, 2, 3]);
+/// ```
+///
+/// # Safety
+///
+/// `Self` must have the same size and alignment as `[T; LEN]`, and any sequence of `LEN` values
+/// of `T` must be a valid `Self`.
+pub unsafe trait HomogeneousHeader<T> {
+    /// The number of values of type `T` in the header.
+    const LEN: usize;
+}
+
+unsafe impl<T> HomogeneousHeader<T> for T {
+    const LEN: usize = 1;
+}
+
+unsafe impl<T, const N: usize> HomogeneousHeader<T> for [T; N] {
+    const LEN: usize = N;
+}
+
+impl<Header: HomogeneousHeader<T>, T> DynStruct<Header, T> {
+    /// Get a `DynStruct` as a view over a slice (this does not allocate). The first elements
+    /// make up the header, and the rest the tail.
     pub fn slice_view(values: &[T]) -> &Self {
-        assert!(
-            values.len() >= N,
-            "attempted to create `{}` from empty slice (needs at least {} elements)",
-            std::any::type_name::<Self>(),
-            N,
-        );
-        let slice = &values[..values.len() - N];
-        unsafe { &*(slice as *const [T] as *const Self) }
+        Self::try_slice_view(values).unwrap_or_else(|error| Self::too_short(error))
     }
+
+    /// Same as [`DynStruct::slice_view`], but returns a mutable view.
+    pub fn slice_view_mut(values: &mut [T]) -> &mut Self {
+        Self::try_slice_view_mut(values).unwrap_or_else(|error| Self::too_short(error))
+    }
+
+    /// Same as [`DynStruct::slice_view`], but returns an error instead of panicking if the
+    /// slice is too short to hold the header.
+    pub fn try_slice_view(values: &[T]) -> Result<&Self, DynStructError> {
+        let len = values.len().saturating_sub(Header::LEN);
+        Self::split_slice_view(values, len).map(|(view, _)| view)
+    }
+
+    /// Same as [`DynStruct::slice_view_mut`], but returns an error instead of panicking if
+    /// the slice is too short to hold the header.
+    pub fn try_slice_view_mut(values: &mut [T]) -> Result<&mut Self, DynStructError> {
+        let len = values.len().saturating_sub(Header::LEN);
+        Self::split_slice_view_mut(values, len).map(|(view, _)| view)
+    }
+
+    /// View the start of a slice as a `DynStruct` with a tail of `len` elements, and return
+    /// it together with the remaining elements. Returns an error if the slice is too short.
+    pub fn split_slice_view(values: &[T], len: usize) -> Result<(&Self, &[T]), DynStructError> {
+        let required = Self::required_len(values.len(), len)?;
+        let (view, rest) = values.split_at(required);
+        let view = &view[..len];
+        Ok((unsafe { &*(view as *const [T] as *const Self) }, rest))
+    }
+
+    /// Same as [`DynStruct::split_slice_view`], but returns mutable references.
+    pub fn split_slice_view_mut(
+        values: &mut [T],
+        len: usize,
+    ) -> Result<(&mut Self, &mut [T]), DynStructError> {
+        let required = Self::required_len(values.len(), len)?;
+        let (view, rest) = values.split_at_mut(required);
+        let view = &mut view[..len];
+        Ok((unsafe { &mut *(view as *mut [T] as *mut Self) }, rest))
+    }
+
+    /// The number of elements needed to view a `DynStruct` with a tail of `len` elements.
+    fn required_len(available: usize, len: usize) -> Result<usize, DynStructError> {
+        match len.checked_add(Header::LEN) {
+            Some(required) if required <= available => Ok(required),
+            _ => Err(DynStructError::SliceTooShort {
+                len: available,
+                required: len.saturating_add(Header::LEN),
+            }),
+        }
+    }
+
+    #[cold]
+    #[track_caller]
+    fn too_short(error: DynStructError) -> ! {
+        panic!(
+            "attempted to create `{}` from too short slice: {}",
+            core::any::type_name::<Self>(),
+            error
+        )
+    }
+
+    /// Reinterpret a boxed slice as a `DynStruct` (this does not allocate or copy). The first
+    /// elements make up the header, and the rest the tail.
+    pub fn from_boxed_slice(values: Box<[T]>) -> Box<Self> {
+        Self::try_from_boxed_slice(values).unwrap_or_else(|values| {
+            Self::too_short(DynStructError::SliceTooShort {
+                len: values.len(),
+                required: Header::LEN,
+            })
+        })
+    }
+
+    /// Same as [`DynStruct::from_boxed_slice`], but gives the slice back instead of panicking
+    /// if it is too short to hold the header.
+    pub fn try_from_boxed_slice(values: Box<[T]>) -> Result<Box<Self>, Box<[T]>> {
+        if values.len() < Header::LEN {
+            return Err(values);
+        }
+        let len = values.len() - Header::LEN;
+        let raw = Box::into_raw(values) as *mut T;
+        Ok(unsafe { Box::from_raw(dyn_struct_ptr(raw.cast(), len)) })
+    }
+
+    /// Same as [`DynStruct::from_boxed_slice`], but takes a `Vec`. Only reallocates if the
+    /// vector has excess capacity, see [`Vec::into_boxed_slice`].
+    pub fn from_vec(values: Vec<T>) -> Box<Self> {
+        Self::from_boxed_slice(values.into_boxed_slice())
+    }
+
+    /// Reinterpret the `DynStruct` as a boxed slice, starting with the elements of the header
+    /// (this does not allocate or copy).
+    pub fn into_boxed_slice(self: Box<Self>) -> Box<[T]> {
+        let len = self.tail.len() + Header::LEN;
+        let raw = Box::into_raw(self) as *mut T;
+        unsafe { Box::from_raw(core::ptr::slice_from_raw_parts_mut(raw, len)) }
+    }
+
+    /// Same as [`DynStruct::into_boxed_slice`], but returns a `Vec`.
+    pub fn into_vec(self: Box<Self>) -> Vec<T> {
+        self.into_boxed_slice().into_vec()
+    }
+}
+
+/// Types with the same layout as a `DynStruct<Self::Header, Self::Tail>`.
+///
+/// Implemented for `DynStruct` itself and by the `DynStruct` derive, where `Header` is a hidden
+/// `#[repr(C)]` struct holding all fields but the last. This lets containers that store a
+/// `DynStruct`, such as [`ThinArc`], hold derived types as well.
+///
+/// # Safety
+///
+/// `Self` must be a `#[repr(C)]` struct whose last field is a `[Self::Tail]`, with the same size,
+/// alignment and field offsets as `DynStruct<Self::Header, Self::Tail>` for every tail length.
+///
+/// The derive ensures this by rejecting structs where the trailing padding of the last sized field
+/// would overlap the tail, since a `DynStruct` always starts its tail after the padded header:
+///
+/// ```compile_fail
+/// # use dyn_struct::DynStruct;
+/// #[repr(C)]
+/// #[derive(DynStruct)]
+/// struct Padded {
+///     id: u32,
+///     flag: u8,
+///     // would start at offset 5, but the tail of the `DynStruct` starts at offset 8
+///     bytes: [u8],
+/// }
+///
+/// let value = Padded::new(1, 2, [3, 4]);
+/// ```
+///
+/// It also rejects any representation other than exactly `#[repr(C)]`, since the hidden header
+/// never has it, so neither `packed`
+///
+/// ```compile_fail
+/// # use dyn_struct::DynStruct;
+/// #[repr(C, packed)]
+/// #[derive(DynStruct)]
+/// struct Packed {
+///     a: u8,
+///     b: u32,
+///     tail: [u16],
+/// }
+/// ```
+///
+/// nor `align` are allowed:
+///
+/// ```compile_fail
+/// # use dyn_struct::DynStruct;
+/// #[repr(C)]
+/// #[repr(align(64))]
+/// #[derive(DynStruct)]
+/// struct Overaligned {
+///     a: u32,
+///     tail: [u32],
+/// }
+/// ```
+pub unsafe trait DynStructLike {
+    type Header;
+    type Tail;
+
+    /// View a `DynStruct` as `Self`.
+    fn from_dyn_struct(value: &DynStruct<Self::Header, Self::Tail>) -> &Self;
+
+    /// View a `DynStruct` as `Self`, mutably.
+    fn from_dyn_struct_mut(value: &mut DynStruct<Self::Header, Self::Tail>) -> &mut Self;
+
+    /// View `self` as a `DynStruct`.
+    fn as_dyn_struct(&self) -> &DynStruct<Self::Header, Self::Tail>;
+
+    /// View `self` as a `DynStruct`, mutably.
+    fn as_dyn_struct_mut(&mut self) -> &mut DynStruct<Self::Header, Self::Tail>;
 }
 
-fn slice_with_len(len: usize) -> &'static [()] {
-    static ARBITRARY: [(); usize::MAX] = [(); usize::MAX];
-    &ARBITRARY[..len]
+unsafe impl<Header, Tail> DynStructLike for DynStruct<Header, Tail> {
+    type Header = Header;
+    type Tail = Tail;
+
+    #[inline]
+    fn from_dyn_struct(value: &Self) -> &Self {
+        value
+    }
+
+    #[inline]
+    fn from_dyn_struct_mut(value: &mut Self) -> &mut Self {
+        value
+    }
+
+    #[inline]
+    fn as_dyn_struct(&self) -> &Self {
+        self
+    }
+
+    #[inline]
+    fn as_dyn_struct_mut(&mut self) -> &mut Self {
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::borrow::Cow;
 
     #[test]
     fn mixed_types() {
@@ -332,6 +1379,326 @@ mod tests {
         assert_eq!(&array.tail, &[4, 5]);
     }
 
+    #[test]
+    fn slice_view_mut() {
+        let mut values = [1u32, 2, 3, 4, 5, 6];
+
+        let view = DynStruct::<u32, u32>::slice_view_mut(&mut values);
+        view.header = 10;
+        view.tail[0] = 20;
+        assert_eq!(values, [10, 20, 3, 4, 5, 6]);
+
+        let (record, rest) =
+            DynStruct::<[u32; 2], u32>::split_slice_view_mut(&mut values, 1).unwrap();
+        record.header[1] = 0;
+        assert_eq!(&record.tail, &[3]);
+        assert_eq!(rest, &[4, 5, 6]);
+
+        let (record, rest) = DynStruct::<u32, u32>::split_slice_view(rest, 2).unwrap();
+        assert_eq!(record.header, 4);
+        assert_eq!(&record.tail, &[5, 6]);
+        assert!(rest.is_empty());
+
+        assert_eq!(
+            DynStruct::<u32, u32>::split_slice_view(&values, 6).unwrap_err(),
+            DynStructError::SliceTooShort {
+                len: 6,
+                required: 7
+            }
+        );
+        assert_eq!(
+            DynStruct::<[u32; 3], u32>::try_slice_view_mut(&mut values[..2]).unwrap_err(),
+            DynStructError::SliceTooShort {
+                len: 2,
+                required: 3
+            }
+        );
+        assert!(DynStruct::<u32, u32>::try_slice_view(&[]).is_err());
+        assert!(DynStruct::<u32, u32>::split_slice_view(&values, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn reference_counted() {
+        let rc = DynStruct::new_rc((true, 32u16), [1u64, 2, 3, 4]);
+        assert_eq!(rc.header, (true, 32u16));
+        assert_eq!(&rc.tail, &[1, 2, 3, 4]);
+
+        let arc = DynStruct::from_slice_arc(7u8, &[1u32, 2, 3]);
+        let shared = Arc::clone(&arc);
+        assert_eq!(shared.header, 7);
+        assert_eq!(&shared.tail, &[1, 2, 3]);
+
+        let zero = DynStruct::new_arc((), [(), ()]);
+        assert_eq!(&zero.tail, &[(), ()]);
+    }
+
+    #[test]
+    fn layout() {
+        fn check<Header, Tail>(value: &DynStruct<Header, Tail>) {
+            let len = value.tail.len();
+            let layout = DynStruct::<Header, Tail>::layout_for(len).unwrap();
+            assert_eq!(layout, Layout::for_value(value));
+
+            let tail_offset =
+                value.tail.as_ptr() as usize - value as *const _ as *const u8 as usize;
+            assert_eq!(DynStruct::<Header, Tail>::tail_offset(), tail_offset);
+
+            if size_of::<Tail>() != 0 {
+                let inverse = DynStruct::<Header, Tail>::len_for_size(layout.size()).unwrap();
+                assert!(inverse >= len);
+                let layout = DynStruct::<Header, Tail>::layout_for(inverse).unwrap();
+                assert_eq!(layout, Layout::for_value(value));
+            }
+        }
+
+        check(&DynStruct::new(1u64, [2u8, 3, 4]));
+        check(&DynStruct::new((1u8, 2u32), [3u16; 5]));
+        check(&DynStruct::new(1u8, [2u64, 3]));
+        check(&DynStruct::new((), [1u8]));
+        check(&DynStruct::new(1u32, [(); 3]));
+        check(&DynStruct::<u16, u8>::new(1, []));
+
+        assert!(DynStruct::<u8, u64>::layout_for(usize::MAX / 4).is_err());
+        assert_eq!(DynStruct::<u8, ()>::len_for_size(100), None);
+    }
+
+    #[test]
+    fn into_parts() {
+        let value = DynStruct::new(String::from("header"), vec![String::from("a"), "b".into()]);
+        let (header, tail) = value.into_parts();
+        assert_eq!(header, "header");
+        assert_eq!(tail, ["a", "b"]);
+
+        let value = Box::<DynStruct<_, _>>::from((header, tail));
+        assert_eq!(value.header, "header");
+        assert_eq!(&value.tail, &["a", "b"]);
+        assert_eq!(value.into_iter().collect::<String>(), "ab");
+
+        let value = Box::<DynStruct<_, _>>::from((1u8, vec![2u16, 3].into_boxed_slice()));
+        assert_eq!(&value.tail, &[2, 3]);
+
+        let counter = Rc::new(());
+        let value = DynStruct::new(Rc::clone(&counter), vec![Rc::clone(&counter); 3]);
+        let header = value.into_header();
+        assert_eq!(Rc::strong_count(&counter), 2);
+        drop(header);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn copy_on_write() {
+        let mut rc = DynStruct::new_rc(1u8, [2u32, 3]);
+        let ptr = Rc::as_ptr(&rc);
+        DynStruct::make_mut_rc(&mut rc).tail[0] = 20;
+        assert_eq!(Rc::as_ptr(&rc), ptr);
+
+        let shared = Rc::clone(&rc);
+        DynStruct::make_mut_rc(&mut rc).header = 10;
+        assert_eq!(rc.header, 10);
+        assert_eq!(shared.header, 1);
+        assert_eq!(&shared.tail, &[20, 3]);
+
+        let mut arc = DynStruct::new_arc((), [String::from("a")]);
+        let weak = Arc::downgrade(&arc);
+        DynStruct::make_mut_arc(&mut arc).tail[0].push('b');
+        assert_eq!(&arc.tail, &["ab"]);
+        assert!(weak.upgrade().is_none());
+
+        let values = [1u32, 2, 3];
+        let mut cow = Cow::Borrowed(DynStruct::<u32, u32>::slice_view(&values));
+        assert_eq!(cow.header, 1);
+        cow.to_mut().tail[1] = 30;
+        assert_eq!(&cow.tail, &[2, 30]);
+        assert_eq!(values, [1, 2, 3]);
+    }
+
+    #[test]
+    fn fallible() {
+        struct Lying(std::ops::Range<u32>, usize);
+        impl Iterator for Lying {
+            type Item = u32;
+            fn next(&mut self) -> Option<u32> {
+                self.0.next()
+            }
+        }
+        impl ExactSizeIterator for Lying {
+            fn len(&self) -> usize {
+                self.1
+            }
+        }
+
+        let too_few = DynStruct::try_new((), Lying(0..2, 3));
+        assert_eq!(
+            too_few.unwrap_err(),
+            DynStructError::LengthMismatch {
+                expected: 3,
+                actual: 2
+            }
+        );
+
+        let too_many = DynStruct::try_new((), Lying(0..5, 3));
+        assert_eq!(
+            too_many.unwrap_err(),
+            DynStructError::LengthMismatch {
+                expected: 3,
+                actual: 4
+            }
+        );
+
+        let overflow =
+            DynStruct::<u8, [u32; 2]>::try_new(0, Lying(0..0, usize::MAX / 4).map(|i| [i, i]));
+        assert_eq!(overflow.unwrap_err(), DynStructError::SizeOverflow);
+
+        let exact = DynStruct::try_from_slice(1u8, &[1u32, 2, 3]).unwrap();
+        assert_eq!(&exact.tail, &[1, 2, 3]);
+
+        assert_eq!(
+            DynStruct::<[u32; 3], u32>::try_slice_view(&[1, 2]).unwrap_err(),
+            DynStructError::SliceTooShort {
+                len: 2,
+                required: 3
+            }
+        );
+        assert!(DynStruct::<u32, u32>::try_slice_view(&[]).is_err());
+    }
+
+    #[test]
+    fn from_iter() {
+        let evens = DynStruct::from_iter("evens", (0..100u32).filter(|x| x % 2 == 0));
+        assert_eq!(evens.header, "evens");
+        assert_eq!(evens.tail.len(), 50);
+        assert!(evens.tail.iter().copied().eq((0..100).step_by(2)));
+
+        let chars: Box<DynStruct<u64, char>> = "hello".chars().collect();
+        assert_eq!(chars.header, 0);
+        assert_eq!(&chars.tail, &['h', 'e', 'l', 'l', 'o']);
+
+        let empty = DynStruct::from_iter((), std::iter::empty::<String>());
+        assert!(empty.tail.is_empty());
+
+        let odds = DynStruct::from_iter((), (0..10u8).filter(|x| x % 2 == 1));
+        assert_eq!(&odds.tail, &[1, 3, 5, 7, 9]);
+
+        let strings = DynStruct::from_iter(1u8, (0..10).flat_map(|i| vec![i.to_string(); i]));
+        assert_eq!(strings.tail.len(), 45);
+        assert_eq!(strings.tail[44], "9");
+    }
+
+    #[test]
+    fn try_from_iter() {
+        let parsed = DynStruct::try_from_iter("numbers", "1 2 3".split(' ').map(str::parse::<u8>));
+        let parsed = parsed.unwrap();
+        assert_eq!(parsed.header, "numbers");
+        assert_eq!(&parsed.tail, &[1, 2, 3]);
+
+        let strings = ["1", "2", "three", "4"]
+            .iter()
+            .map(|s| match s.parse::<u8>() {
+                Ok(_) => Ok(s.to_string()),
+                Err(_) => Err(format!("bad number: {}", s)),
+            });
+        let failed = DynStruct::try_from_iter(String::from("header"), strings);
+        assert_eq!(failed.unwrap_err(), "bad number: three");
+
+        let counter = Rc::new(());
+        let items = (0..10).map(|i| match i {
+            5 => Err(i),
+            _ => Ok(Rc::clone(&counter)),
+        });
+        assert_eq!(
+            DynStruct::try_from_iter(Rc::clone(&counter), items).unwrap_err(),
+            5
+        );
+        assert_eq!(
+            Rc::strong_count(&counter),
+            1,
+            "partial tail should be dropped"
+        );
+    }
+
+    #[test]
+    fn clone() {
+        let original = DynStruct::new(String::from("header"), vec![String::from("a"); 3]);
+        let clone = original.clone();
+        assert_eq!(clone, original);
+
+        let copy = DynStruct::from_slice((1u8, 2u32), &[3u64, 4]).clone();
+        assert_eq!(copy.header, (1, 2));
+        assert_eq!(&copy.tail, &[3, 4]);
+
+        struct Fragile(Rc<()>);
+        impl Clone for Fragile {
+            fn clone(&self) -> Self {
+                assert!(Rc::strong_count(&self.0) < 5, "too many clones");
+                Fragile(Rc::clone(&self.0))
+            }
+        }
+
+        let counter = Rc::new(());
+        let fragile = DynStruct::new((), vec![Fragile(Rc::clone(&counter)); 3]);
+        assert_eq!(Rc::strong_count(&counter), 4);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| fragile.clone()));
+        assert!(result.is_err());
+        assert_eq!(
+            Rc::strong_count(&counter),
+            4,
+            "partial clone should be dropped"
+        );
+    }
+
+    #[test]
+    fn from_fn() {
+        let squares = DynStruct::from_fn("squares", 5, |i| i * i);
+        assert_eq!(squares.header, "squares");
+        assert_eq!(&squares.tail, &[0, 1, 4, 9, 16]);
+
+        let scaled = DynStruct::from_fn_with_header(3u32, 4, |scale, i| *scale * i as u32);
+        assert_eq!(&scaled.tail, &[0, 3, 6, 9]);
+
+        let repeated = DynStruct::repeat((), String::from("x"), 3);
+        assert_eq!(&repeated.tail, &["x", "x", "x"]);
+
+        let cloned = DynStruct::from_slice_cloned(1u8, &[String::from("a"), String::from("b")]);
+        assert_eq!(&cloned.tail, &["a", "b"]);
+    }
+
+    #[test]
+    fn uninit() {
+        let mut uninit = DynStruct::<u8, u32>::new_uninit(7, 4);
+        for (i, value) in uninit.tail.iter_mut().enumerate() {
+            value.write(i as u32 * 10);
+        }
+        let init = unsafe { uninit.assume_init() };
+        assert_eq!(init.header, 7);
+        assert_eq!(&init.tail, &[0, 10, 20, 30]);
+
+        let zeroed = DynStruct::<(u8, u16), u64>::new_zeroed((1, 2), 1000);
+        let zeroed = unsafe { zeroed.assume_init() };
+        assert_eq!(zeroed.header, (1, 2));
+        assert!(zeroed.tail.iter().all(|&x| x == 0));
+    }
+
+    #[test]
+    fn grow_and_shrink() {
+        let value = DynStruct::new(String::from("log"), vec![String::from("a")]);
+        let value = value.push(String::from("b"));
+        assert_eq!(&value.tail, &["a", "b"]);
+
+        let value = value.extend((0..5).map(|i| i.to_string()));
+        assert_eq!(value.tail.len(), 7);
+        assert_eq!(value.tail[6], "4");
+
+        let value = value.truncate(3);
+        assert_eq!(value.header, "log");
+        assert_eq!(&value.tail, &["a", "b", "0"]);
+
+        let value = value.truncate(0).push(String::from("c"));
+        assert_eq!(&value.tail, &["c"]);
+
+        let zero = DynStruct::new((), []).push(()).extend([(), ()]).truncate(1);
+        assert_eq!(&zero.tail, &[()]);
+    }
+
     #[test]
     fn slice_view() {
         let same = DynStruct::<u32, u32>::slice_view(&[1, 2, 3]);
@@ -342,4 +1709,28 @@ mod tests {
         assert_eq!(array.header, [1, 2, 3]);
         assert_eq!(&array.tail, &[4, 5]);
     }
+
+    #[test]
+    fn boxed_slice() {
+        let values: Box<[String]> = vec!["a".into(), "b".into(), "c".into()].into_boxed_slice();
+        let ptr = values.as_ptr();
+        let same = DynStruct::<String, String>::from_boxed_slice(values);
+        assert_eq!(same.header, "a");
+        assert_eq!(&same.tail, &["b", "c"]);
+        assert_eq!(&same.header as *const String, ptr);
+
+        let values = same.into_vec();
+        assert_eq!(values, ["a", "b", "c"]);
+        assert_eq!(values.as_ptr(), ptr);
+
+        let array = DynStruct::<[u32; 2], u32>::from_vec(vec![1, 2, 3]);
+        assert_eq!(array.header, [1, 2]);
+        assert_eq!(&array.tail, &[3]);
+        assert_eq!(&*array.into_boxed_slice(), &[1, 2, 3]);
+
+        let short = DynStruct::<[u32; 2], u32>::try_from_boxed_slice(Box::new([1]));
+        assert_eq!(&*short.unwrap_err(), &[1]);
+        assert!(DynStruct::<u8, u8>::try_from_boxed_slice(Box::new([])).is_err());
+        assert_eq!(DynStruct::<(), ()>::from_vec(vec![(); 3]).tail.len(), 2);
+    }
 }
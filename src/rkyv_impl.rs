@@ -0,0 +1,179 @@
+//! `rkyv` support, enabled by the `rkyv` feature.
+//!
+//! A `DynStruct<Header, Tail>` is archived as an [`ArchivedDynStruct`] with the archived header and
+//! tail, which can be accessed in place. `Box<DynStruct>` deserializes from its archived form with a
+//! single allocation.
+
+use crate::DynStruct;
+use alloc::boxed::Box;
+use core::fmt;
+use rkyv::boxed::ArchivedBox;
+use rkyv::bytecheck::CheckBytes;
+use rkyv::primitive::ArchivedUsize;
+use rkyv::ptr_meta::Pointee;
+use rkyv::rancor::{Fallible, Source, Trace};
+use rkyv::ser::{Allocator, Writer, WriterExt};
+use rkyv::traits::{ArchivePointee, LayoutRaw};
+use rkyv::util::SerVec;
+use rkyv::{Archive, ArchiveUnsized, Archived, Deserialize, Portable, Serialize, SerializeUnsized};
+
+/// The archived form of a [`DynStruct`](struct@DynStruct), with the same layout as a
+/// `DynStruct<Header::Archived, Tail::Archived>`.
+#[repr(C)]
+pub struct ArchivedDynStruct<Header, Tail> {
+    pub header: Header,
+    pub tail: [Tail],
+}
+
+unsafe impl<Header, Tail> Pointee for DynStruct<Header, Tail> {
+    type Metadata = usize;
+}
+
+unsafe impl<Header, Tail> Pointee for ArchivedDynStruct<Header, Tail> {
+    type Metadata = usize;
+}
+
+// SAFETY: `ArchivedDynStruct` is `repr(C)` and only contains portable types.
+unsafe impl<Header: Portable, Tail: Portable> Portable for ArchivedDynStruct<Header, Tail> {}
+
+impl<Header, Tail> ArchivePointee for ArchivedDynStruct<Header, Tail> {
+    type ArchivedMetadata = ArchivedUsize;
+
+    fn pointer_metadata(archived: &Self::ArchivedMetadata) -> usize {
+        archived.to_native() as usize
+    }
+}
+
+impl<Header, Tail> LayoutRaw for ArchivedDynStruct<Header, Tail> {
+    fn layout_raw(len: usize) -> Result<core::alloc::Layout, core::alloc::LayoutError> {
+        DynStruct::<Header, Tail>::layout_for(len)
+    }
+}
+
+unsafe impl<Header, Tail, C> CheckBytes<C> for ArchivedDynStruct<Header, Tail>
+where
+    Header: CheckBytes<C>,
+    Tail: CheckBytes<C>,
+    C: Fallible + ?Sized,
+    C::Error: Trace,
+{
+    unsafe fn check_bytes(value: *const Self, context: &mut C) -> Result<(), C::Error> {
+        Header::check_bytes(core::ptr::addr_of!((*value).header), context)?;
+        <[Tail]>::check_bytes(core::ptr::addr_of!((*value).tail), context)
+    }
+}
+
+impl<Header: fmt::Debug, Tail: fmt::Debug> fmt::Debug for ArchivedDynStruct<Header, Tail> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchivedDynStruct")
+            .field("header", &self.header)
+            .field("tail", &&self.tail)
+            .finish()
+    }
+}
+
+impl<Header, Tail, H, T> PartialEq<DynStruct<H, T>> for ArchivedDynStruct<Header, Tail>
+where
+    Header: PartialEq<H>,
+    Tail: PartialEq<T>,
+{
+    fn eq(&self, other: &DynStruct<H, T>) -> bool {
+        self.header == other.header && self.tail == other.tail
+    }
+}
+
+impl<Header: Archive, Tail: Archive> ArchiveUnsized for DynStruct<Header, Tail> {
+    type Archived = ArchivedDynStruct<Header::Archived, Tail::Archived>;
+
+    fn archived_metadata(&self) -> ArchivedUsize {
+        ArchivedUsize::from_native(self.tail.len() as _)
+    }
+}
+
+impl<Header, Tail, S> SerializeUnsized<S> for DynStruct<Header, Tail>
+where
+    Header: Serialize<S>,
+    Tail: Serialize<S>,
+    S: Fallible + Allocator + Writer + ?Sized,
+{
+    fn serialize_unsized(&self, serializer: &mut S) -> Result<usize, S::Error> {
+        let header = self.header.serialize(serializer)?;
+
+        SerVec::with_capacity(serializer, self.tail.len(), |resolvers, serializer| {
+            for value in self.tail.iter() {
+                unsafe { resolvers.push_unchecked(value.serialize(serializer)?) };
+            }
+
+            // The header is followed by padding up to the alignment of the tail, and the tail by
+            // padding up to the alignment of the whole struct, just like in a `DynStruct`.
+            let align = DynStruct::<Header::Archived, Tail::Archived>::align();
+            let position = serializer.align(align)?;
+            unsafe { serializer.resolve_aligned(&self.header, header)? };
+            serializer.align_for::<Tail::Archived>()?;
+            for (value, resolver) in self.tail.iter().zip(resolvers.drain()) {
+                unsafe { serializer.resolve_aligned(value, resolver)? };
+            }
+            serializer.align(align)?;
+
+            Ok(position)
+        })?
+    }
+}
+
+impl<Header, Tail, D> Deserialize<Box<DynStruct<Header, Tail>>, D>
+    for ArchivedBox<ArchivedDynStruct<Archived<Header>, Archived<Tail>>>
+where
+    Header: Archive,
+    Tail: Archive,
+    Archived<Header>: Deserialize<Header, D>,
+    Archived<Tail>: Deserialize<Tail, D>,
+    D: Fallible + ?Sized,
+    D::Error: Source,
+{
+    /// Deserialize the header, and then the tail directly into the allocation.
+    fn deserialize(&self, deserializer: &mut D) -> Result<Box<DynStruct<Header, Tail>>, D::Error> {
+        let archived = self.get();
+        let header = archived.header.deserialize(deserializer)?;
+        let tail = archived
+            .tail
+            .iter()
+            .map(|value| value.deserialize(deserializer));
+        DynStruct::write_unsized_iter(header, tail).map_err(D::Error::new)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rkyv::rancor::Error;
+
+    #[test]
+    fn round_trip() {
+        let value = DynStruct::new((1u8, String::from("two")), vec![3u64, 4, 5]);
+
+        let bytes = rkyv::to_bytes::<Error>(&value).unwrap();
+        let archived =
+            rkyv::access::<Archived<Box<DynStruct<(u8, String), u64>>>, Error>(&bytes).unwrap();
+        assert_eq!(archived.header.1, "two");
+        assert_eq!(archived.tail, [3, 4, 5]);
+        assert_eq!(*archived, value);
+
+        let back = rkyv::deserialize::<Box<DynStruct<(u8, String), u64>>, Error>(archived).unwrap();
+        assert_eq!(back, value);
+    }
+
+    #[test]
+    fn padding() {
+        // the header has trailing padding
+        let value = DynStruct::new((7u32, 1u8), vec![2u8, 3, 4, 5, 6]);
+        let bytes = rkyv::to_bytes::<Error>(&value).unwrap();
+        let archived =
+            rkyv::access::<Archived<Box<DynStruct<(u32, u8), u8>>>, Error>(&bytes).unwrap();
+        assert_eq!(*archived, value);
+
+        let empty = DynStruct::new((), Vec::<u16>::new());
+        let bytes = rkyv::to_bytes::<Error>(&empty).unwrap();
+        let back = rkyv::from_bytes::<Box<DynStruct<(), u16>>, Error>(&bytes).unwrap();
+        assert!(back.tail.is_empty());
+    }
+}
@@ -0,0 +1,327 @@
+//! `Serialize` and `Deserialize` implementations, enabled by the `serde` feature.
+//!
+//! A `DynStruct` is (de)serialized like a struct with the two fields `header` and `tail`, where the
+//! tail is a sequence. Types using the `DynStruct` derive with `#[dyn_struct(serde)]` are instead
+//! (de)serialized like a regular struct with the same fields.
+
+use crate::DynStruct;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+
+impl<Header: Serialize, Tail: Serialize> Serialize for DynStruct<Header, Tail> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("DynStruct", 2)?;
+        state.serialize_field("header", &self.header)?;
+        state.serialize_field("tail", &self.tail)?;
+        state.end()
+    }
+}
+
+impl<'de, Header, Tail> Deserialize<'de> for Box<DynStruct<Header, Tail>>
+where
+    Header: Deserialize<'de>,
+    Tail: Deserialize<'de>,
+{
+    /// Deserialize a `DynStruct`, writing the elements of the tail directly into the allocation
+    /// (unless the tail comes before the header, in which case it has to be buffered first).
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = deserialize::<D, Whole<Header>, Tail>(deserializer)?;
+        // `Whole` is a transparent wrapper, so the layouts are identical
+        Ok(unsafe { Box::from_raw(Box::into_raw(value) as *mut DynStruct<Header, Tail>) })
+    }
+}
+
+/// A header which is (de)serialized as a whole, as the `header` field of a `DynStruct`.
+#[repr(transparent)]
+struct Whole<Header>(Header);
+
+impl<'de, Header: Deserialize<'de>> DeserializeHeader<'de> for Whole<Header> {
+    const NAME: &'static str = "DynStruct";
+    const FIELDS: &'static [&'static str] = &["header", "tail"];
+
+    type Partial = Option<Header>;
+
+    fn deserialize_field<A: MapAccess<'de>>(
+        partial: &mut Self::Partial,
+        _index: usize,
+        map: &mut A,
+    ) -> Result<(), A::Error> {
+        set_field(partial, map.next_value()?, "header")
+    }
+
+    fn next_element<A: SeqAccess<'de>>(
+        partial: &mut Self::Partial,
+        _index: usize,
+        seq: &mut A,
+    ) -> Result<bool, A::Error> {
+        *partial = seq.next_element()?;
+        Ok(partial.is_some())
+    }
+
+    fn is_complete(partial: &Self::Partial) -> bool {
+        partial.is_some()
+    }
+
+    fn build<E: de::Error>(partial: Self::Partial) -> Result<Self, E> {
+        partial.map(Whole).ok_or_else(|| E::missing_field("header"))
+    }
+}
+
+/// A header deserialized field by field, implemented by the `DynStruct` derive.
+#[doc(hidden)]
+pub trait DeserializeHeader<'de>: Sized {
+    /// The name of the struct.
+    const NAME: &'static str;
+
+    /// The names of all fields, with the tail last.
+    const FIELDS: &'static [&'static str];
+
+    /// The fields deserialized so far.
+    type Partial: Default;
+
+    /// Deserialize the value of the field at `index` from the map.
+    fn deserialize_field<A: MapAccess<'de>>(
+        partial: &mut Self::Partial,
+        index: usize,
+        map: &mut A,
+    ) -> Result<(), A::Error>;
+
+    /// Deserialize the field at `index` from the next element of the sequence. Returns `false` if
+    /// the sequence has ended.
+    fn next_element<A: SeqAccess<'de>>(
+        partial: &mut Self::Partial,
+        index: usize,
+        seq: &mut A,
+    ) -> Result<bool, A::Error>;
+
+    /// Returns `true` if every field has been deserialized.
+    fn is_complete(partial: &Self::Partial) -> bool;
+
+    fn build<E: de::Error>(partial: Self::Partial) -> Result<Self, E>;
+}
+
+/// Store the value of a field, unless it has already been deserialized.
+#[doc(hidden)]
+pub fn set_field<T, E: de::Error>(
+    slot: &mut Option<T>,
+    value: T,
+    name: &'static str,
+) -> Result<(), E> {
+    if slot.is_some() {
+        return Err(E::duplicate_field(name));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+/// Deserialize a `DynStruct` whose header is deserialized field by field.
+#[doc(hidden)]
+pub fn deserialize<'de, D, Header, Tail>(
+    deserializer: D,
+) -> Result<Box<DynStruct<Header, Tail>>, D::Error>
+where
+    D: Deserializer<'de>,
+    Header: DeserializeHeader<'de>,
+    Tail: Deserialize<'de>,
+{
+    deserializer.deserialize_struct(Header::NAME, Header::FIELDS, DynStructVisitor(PhantomData))
+}
+
+struct DynStructVisitor<Header, Tail>(PhantomData<fn() -> (Header, Tail)>);
+
+impl<'de, Header, Tail> Visitor<'de> for DynStructVisitor<Header, Tail>
+where
+    Header: DeserializeHeader<'de>,
+    Tail: Deserialize<'de>,
+{
+    type Value = Box<DynStruct<Header, Tail>>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "struct {}", Header::NAME)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let header_fields = Header::FIELDS.len() - 1;
+
+        let mut partial = Header::Partial::default();
+        for index in 0..header_fields {
+            if !Header::next_element(&mut partial, index, &mut seq)? {
+                return Err(de::Error::invalid_length(index, &self));
+            }
+        }
+        let header = Header::build(partial)?;
+
+        seq.next_element_seed(TailSeed(header, PhantomData))?
+            .ok_or_else(|| de::Error::invalid_length(header_fields, &self))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let tail_index = Header::FIELDS.len() - 1;
+        let tail_name = Header::FIELDS[tail_index];
+
+        let mut partial = Header::Partial::default();
+        let mut value = None;
+        let mut buffered: Option<Vec<Tail>> = None;
+
+        while let Some(index) = map.next_key_seed(FieldSeed(Header::FIELDS))? {
+            match index {
+                Some(index) if index == tail_index => {
+                    if value.is_some() || buffered.is_some() {
+                        return Err(de::Error::duplicate_field(tail_name));
+                    }
+                    if Header::is_complete(&partial) {
+                        let header = Header::build(core::mem::take(&mut partial))?;
+                        value = Some(map.next_value_seed(TailSeed(header, PhantomData))?);
+                    } else {
+                        buffered = Some(map.next_value()?);
+                    }
+                }
+                Some(index) => {
+                    if value.is_some() {
+                        // the header was complete before the tail, so we have seen this already
+                        return Err(de::Error::duplicate_field(Header::FIELDS[index]));
+                    }
+                    Header::deserialize_field(&mut partial, index, &mut map)?;
+                }
+                None => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+
+        match (value, buffered) {
+            (Some(value), _) => Ok(value),
+            (None, Some(tail)) => Ok(Box::from((Header::build(partial)?, tail))),
+            (None, None) => Err(de::Error::missing_field(tail_name)),
+        }
+    }
+}
+
+/// Deserializes the name of a field into its index, or `None` if it is unknown.
+struct FieldSeed(&'static [&'static str]);
+
+impl<'de> DeserializeSeed<'de> for FieldSeed {
+    type Value = Option<usize>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_identifier(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FieldSeed {
+    type Value = Option<usize>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a field identifier")
+    }
+
+    fn visit_u64<E: de::Error>(self, index: u64) -> Result<Self::Value, E> {
+        Ok(Some(index as usize).filter(|&index| index < self.0.len()))
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+        Ok(self.0.iter().position(|field| *field == name))
+    }
+
+    fn visit_bytes<E: de::Error>(self, name: &[u8]) -> Result<Self::Value, E> {
+        Ok(self.0.iter().position(|field| field.as_bytes() == name))
+    }
+}
+
+/// Deserializes the tail as a sequence, writing it directly into the allocation.
+struct TailSeed<Header, Tail>(Header, PhantomData<fn() -> Tail>);
+
+impl<'de, Header, Tail: Deserialize<'de>> DeserializeSeed<'de> for TailSeed<Header, Tail> {
+    type Value = Box<DynStruct<Header, Tail>>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, Header, Tail: Deserialize<'de>> Visitor<'de> for TailSeed<Header, Tail> {
+    type Value = Box<DynStruct<Header, Tail>>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        let elements = SeqIter(seq, PhantomData);
+        DynStruct::write_unsized_iter(self.0, elements)
+            .map_err(de::Error::custom)
+            .and_then(|result| result)
+    }
+}
+
+/// The elements of a sequence as an iterator.
+struct SeqIter<'de, A, Tail>(A, PhantomData<fn(&'de ()) -> Tail>);
+
+impl<'de, A: SeqAccess<'de>, Tail: Deserialize<'de>> Iterator for SeqIter<'de, A, Tail> {
+    type Item = Result<Tail, A::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_element().transpose()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Don't trust the length given by the input blindly: it could make us allocate far more
+        // memory than the input would ever fill.
+        const MAX_PREALLOCATED_BYTES: usize = 1024 * 1024;
+        let max = MAX_PREALLOCATED_BYTES / core::mem::size_of::<Tail>().max(1);
+        (self.0.size_hint().unwrap_or(0).min(max), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let value = DynStruct::new((1u8, String::from("two")), vec![3u32, 4, 5]);
+
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(json, r#"{"header":[1,"two"],"tail":[3,4,5]}"#);
+        let back: Box<DynStruct<(u8, String), u32>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, value);
+
+        let bytes = bincode::serialize(&value).unwrap();
+        let back: Box<DynStruct<(u8, String), u32>> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(back, value);
+    }
+
+    #[test]
+    fn field_order() {
+        type Value = Box<DynStruct<u8, char>>;
+
+        let value: Value =
+            serde_json::from_str(r#"{"tail":["a","b"],"extra":0,"header":1}"#).unwrap();
+        assert_eq!(value.header, 1);
+        assert_eq!(&value.tail, &['a', 'b']);
+
+        let value: Value = serde_json::from_str(r#"[1, []]"#).unwrap();
+        assert!(value.tail.is_empty());
+
+        let error = serde_json::from_str::<Value>(r#"{"header":1,"tail":[],"header":2}"#);
+        assert!(error
+            .unwrap_err()
+            .to_string()
+            .contains("duplicate field `header`"));
+        let error = serde_json::from_str::<Value>(r#"{"header":1}"#);
+        assert!(error
+            .unwrap_err()
+            .to_string()
+            .contains("missing field `tail`"));
+        let error = serde_json::from_str::<Value>(r#"{"tail":[]}"#);
+        assert!(error
+            .unwrap_err()
+            .to_string()
+            .contains("missing field `header`"));
+    }
+}
@@ -0,0 +1,633 @@
+use crate::{dyn_struct_ptr, Allocation, DynStruct, DynStructError};
+use alloc::alloc::{alloc, dealloc, Layout};
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+#[cfg(target_has_atomic = "ptr")]
+use core::sync::atomic::{self, AtomicUsize, Ordering};
+
+/// An owned `DynStruct` behind a thin pointer.
+///
+/// A `Box<DynStruct<Header, Tail>>` is a fat pointer, storing the length of the tail next to the
+/// address. A `ThinBox` instead stores the length as a prefix inside the allocation itself, so the
+/// pointer is only a single word wide. The prefix is of type `Len`, which may be any of `u8`,
+/// `u16`, `u32` or `usize`, trading the maximum length of the tail for space.
+///
+/// ```
+/// # use dyn_struct::{DynStruct, ThinBox};
+/// let thin = ThinBox::<_, _, u16>::new(true, [1u32, 2, 3]);
+/// assert!(thin.header);
+/// assert_eq!(&thin.tail, &[1, 2, 3]);
+/// assert_eq!(std::mem::size_of_val(&thin), std::mem::size_of::<usize>());
+/// ```
+pub struct ThinBox<Header, Tail, Len: ThinLength = usize> {
+    ptr: NonNull<ThinDynStruct<Header, Tail, Len>>,
+    _owned: PhantomData<ThinDynStruct<Header, Tail, Len>>,
+}
+
+/// The start of the allocation owned by a [`ThinBox`], which holds the length of the tail and is
+/// followed by the `DynStruct` itself.
+///
+/// This type is only ever used behind raw pointers, such as those returned by
+/// [`ThinBox::into_raw`], which may for example be stored in an
+/// [`AtomicPtr`](core::sync::atomic::AtomicPtr).
+#[repr(C)]
+pub struct ThinDynStruct<Header, Tail, Len: ThinLength = usize> {
+    len: Len,
+    _value: PhantomData<DynStruct<Header, Tail>>,
+}
+
+/// An unsigned integer type that can store the length of the tail in a [`ThinBox`].
+pub trait ThinLength: Copy + private::Sealed {
+    /// The maximum length that can be stored.
+    const MAX: usize;
+
+    #[doc(hidden)]
+    fn from_usize(len: usize) -> Self;
+
+    #[doc(hidden)]
+    fn into_usize(self) -> usize;
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+macro_rules! impl_thin_length {
+    ($($int:ty),*) => {
+        $(
+            impl private::Sealed for $int {}
+
+            impl ThinLength for $int {
+                const MAX: usize = {
+                    if (<$int>::MAX as u128) < usize::MAX as u128 {
+                        <$int>::MAX as usize
+                    } else {
+                        usize::MAX
+                    }
+                };
+
+                #[inline]
+                fn from_usize(len: usize) -> Self {
+                    debug_assert!(len <= <Self as ThinLength>::MAX);
+                    len as $int
+                }
+
+                #[inline]
+                fn into_usize(self) -> usize {
+                    self as usize
+                }
+            }
+        )*
+    };
+}
+
+impl_thin_length!(u8, u16, u32, usize);
+
+impl<Header, Tail, Len: ThinLength> ThinBox<Header, Tail, Len> {
+    /// Allocate a new `DynStruct` behind a thin pointer. Same as [`DynStruct::new`], but panics if
+    /// the length of the tail does not fit in `Len`.
+    #[inline]
+    pub fn new<I>(header: Header, tail: I) -> Self
+    where
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        DynStruct::write_iter::<ThinAlloc<Len>, I>(header, tail, ())
+            .unwrap_or_else(|error| error.raise::<I::IntoIter>())
+    }
+
+    /// Same as [`ThinBox::new`], but returns an error instead of panicking or aborting. See
+    /// [`DynStruct::try_new`].
+    #[inline]
+    pub fn try_new<I>(header: Header, tail: I) -> Result<Self, DynStructError>
+    where
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        DynStruct::write_iter::<ThinAlloc<Len>, I>(header, tail, ())
+    }
+
+    /// Allocate a new `DynStruct` behind a thin pointer by copying the tail from a slice. See
+    /// [`DynStruct::from_slice`].
+    pub fn from_slice(header: Header, tail: &[Tail]) -> Self
+    where
+        Tail: Copy,
+    {
+        DynStruct::write_slice::<ThinAlloc<Len>>(header, tail, ())
+            .unwrap_or_else(|error| error.raise::<()>())
+    }
+
+    /// Consume the `ThinBox`, returning a thin pointer to the allocation. The pointer can be
+    /// converted back with [`ThinBox::from_raw`].
+    #[inline]
+    pub fn into_raw(this: Self) -> *mut ThinDynStruct<Header, Tail, Len> {
+        let ptr = this.ptr.as_ptr();
+        core::mem::forget(this);
+        ptr
+    }
+
+    /// Take back ownership of a pointer returned by [`ThinBox::into_raw`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `ThinBox::into_raw` with the same type parameters, and
+    /// may not be used to construct more than one `ThinBox`.
+    #[inline]
+    pub unsafe fn from_raw(ptr: *mut ThinDynStruct<Header, Tail, Len>) -> Self {
+        ThinBox {
+            ptr: NonNull::new_unchecked(ptr),
+            _owned: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        unsafe { (*self.ptr.as_ptr()).len.into_usize() }
+    }
+
+    #[inline]
+    fn value_ptr(&self) -> *mut DynStruct<Header, Tail> {
+        let data = unsafe {
+            self.ptr
+                .as_ptr()
+                .cast::<u8>()
+                .add(value_offset::<Header, Tail, Len>())
+        };
+        dyn_struct_ptr(data, self.len())
+    }
+}
+
+/// The offset from the start of the allocation to the `DynStruct`.
+#[inline]
+fn value_offset<Header, Tail, Len>() -> usize {
+    let prefix = Layout::new::<Len>();
+    let value = Layout::from_size_align(0, DynStruct::<Header, Tail>::align()).unwrap();
+    prefix.extend(value).unwrap().1
+}
+
+/// The layout of the allocation of a `ThinBox` with a tail of `len` elements.
+#[inline]
+fn thin_layout<Header, Tail, Len>(len: usize) -> Result<Layout, DynStructError> {
+    let value = DynStruct::<Header, Tail>::layout(len)?;
+    let (layout, _) = Layout::new::<Len>()
+        .extend(value)
+        .map_err(|_| DynStructError::SizeOverflow)?;
+    Ok(layout.pad_to_align())
+}
+
+impl<Header, Tail, Len: ThinLength> Deref for ThinBox<Header, Tail, Len> {
+    type Target = DynStruct<Header, Tail>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.value_ptr() }
+    }
+}
+
+impl<Header, Tail, Len: ThinLength> DerefMut for ThinBox<Header, Tail, Len> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.value_ptr() }
+    }
+}
+
+impl<Header, Tail, Len: ThinLength> Drop for ThinBox<Header, Tail, Len> {
+    fn drop(&mut self) {
+        let len = self.len();
+        unsafe {
+            core::ptr::drop_in_place(self.value_ptr());
+            // the layout was valid when we allocated it, so it still is
+            let layout = thin_layout::<Header, Tail, Len>(len).unwrap();
+            dealloc(self.ptr.as_ptr().cast(), layout);
+        }
+    }
+}
+
+// SAFETY: `ThinBox` owns its contents just like a `Box`
+unsafe impl<Header: Send, Tail: Send, Len: ThinLength> Send for ThinBox<Header, Tail, Len> {}
+unsafe impl<Header: Sync, Tail: Sync, Len: ThinLength> Sync for ThinBox<Header, Tail, Len> {}
+
+impl<Header, Tail, Len> fmt::Debug for ThinBox<Header, Tail, Len>
+where
+    Header: fmt::Debug,
+    Tail: fmt::Debug,
+    Len: ThinLength,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// An atomically reference counted `DynStruct` behind a thin pointer.
+///
+/// The reference count, the length of the tail and the `DynStruct` itself all live in a single
+/// allocation, so a `ThinArc` is only a single word wide, unlike an `Arc<DynStruct<Header, Tail>>`.
+/// Weak references are not supported.
+///
+/// ```
+/// # use dyn_struct::ThinArc;
+/// let mut a = ThinArc::new("numbers", [1u32, 2, 3]);
+/// let b = a.clone();
+/// ThinArc::make_mut(&mut a).tail[0] = 10;
+/// assert_eq!(&a.tail, &[10, 2, 3]);
+/// assert_eq!(&b.tail, &[1, 2, 3]);
+/// ```
+#[cfg(target_has_atomic = "ptr")]
+pub struct ThinArc<Header, Tail> {
+    ptr: NonNull<ThinArcInner<Header, Tail>>,
+    _owned: PhantomData<ThinArcInner<Header, Tail>>,
+}
+
+/// The start of the allocation shared by [`ThinArc`]s, which holds the reference count and the
+/// length of the tail and is followed by the `DynStruct` itself.
+///
+/// This type is only ever used behind raw pointers, such as those returned by
+/// [`ThinArc::into_raw`].
+#[cfg(target_has_atomic = "ptr")]
+#[repr(C)]
+pub struct ThinArcInner<Header, Tail> {
+    count: AtomicUsize,
+    len: usize,
+    _value: PhantomData<DynStruct<Header, Tail>>,
+}
+
+/// Same limit as `Arc`: beyond this the count could overflow before we get a chance to abort.
+#[cfg(target_has_atomic = "ptr")]
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+
+#[cfg(target_has_atomic = "ptr")]
+impl<Header, Tail> ThinArc<Header, Tail> {
+    /// Allocate a new reference counted `DynStruct` behind a thin pointer. See [`DynStruct::new`].
+    #[inline]
+    pub fn new<I>(header: Header, tail: I) -> Self
+    where
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        DynStruct::write_iter::<ThinAlloc<ThinArcInner<Header, Tail>>, I>(header, tail, ())
+            .unwrap_or_else(|error| error.raise::<I::IntoIter>())
+    }
+
+    /// Same as [`ThinArc::new`], but returns an error instead of panicking or aborting. See
+    /// [`DynStruct::try_new`].
+    #[inline]
+    pub fn try_new<I>(header: Header, tail: I) -> Result<Self, DynStructError>
+    where
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        DynStruct::write_iter::<ThinAlloc<ThinArcInner<Header, Tail>>, I>(header, tail, ())
+    }
+
+    /// Allocate a new reference counted `DynStruct` behind a thin pointer by copying the tail from
+    /// a slice. See [`DynStruct::from_slice`].
+    pub fn from_slice(header: Header, tail: &[Tail]) -> Self
+    where
+        Tail: Copy,
+    {
+        DynStruct::write_slice::<ThinAlloc<ThinArcInner<Header, Tail>>>(header, tail, ())
+            .unwrap_or_else(|error| error.raise::<()>())
+    }
+
+    /// Consume the `ThinArc` without decrementing the reference count, returning a thin pointer
+    /// to the allocation. The pointer can be converted back with [`ThinArc::from_raw`].
+    #[inline]
+    pub fn into_raw(this: Self) -> *const ThinArcInner<Header, Tail> {
+        let ptr = this.ptr.as_ptr();
+        core::mem::forget(this);
+        ptr
+    }
+
+    /// Take back ownership of a pointer returned by [`ThinArc::into_raw`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `ThinArc::into_raw` with the same type parameters, and
+    /// every call to `into_raw` may be matched by at most one call to `from_raw`.
+    #[inline]
+    pub unsafe fn from_raw(ptr: *const ThinArcInner<Header, Tail>) -> Self {
+        ThinArc {
+            ptr: NonNull::new_unchecked(ptr as *mut _),
+            _owned: PhantomData,
+        }
+    }
+
+    /// The number of `ThinArc`s pointing to this allocation.
+    #[inline]
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().count.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` if both `ThinArc`s point to the same allocation.
+    #[inline]
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    /// Returns a mutable reference to the `DynStruct` if there are no other `ThinArc`s pointing
+    /// to the same allocation.
+    #[inline]
+    pub fn get_mut(this: &mut Self) -> Option<&mut DynStruct<Header, Tail>> {
+        if Self::strong_count(this) == 1 {
+            Some(unsafe { &mut *this.value_ptr() })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the `DynStruct`, first cloning it into a new allocation if
+    /// there are other `ThinArc`s pointing to the same one.
+    pub fn make_mut(this: &mut Self) -> &mut DynStruct<Header, Tail>
+    where
+        Header: Clone,
+        Tail: Clone,
+    {
+        if Self::strong_count(this) != 1 {
+            *this = ThinArc::new(this.header.clone(), this.tail.iter().cloned());
+        }
+        unsafe { &mut *this.value_ptr() }
+    }
+
+    #[inline]
+    fn inner(&self) -> &ThinArcInner<Header, Tail> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    #[inline]
+    fn value_ptr(&self) -> *mut DynStruct<Header, Tail> {
+        let data = unsafe {
+            self.ptr.as_ptr().cast::<u8>().add(value_offset::<
+                Header,
+                Tail,
+                ThinArcInner<Header, Tail>,
+            >())
+        };
+        dyn_struct_ptr(data, self.inner().len)
+    }
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<Header, Tail> Clone for ThinArc<Header, Tail> {
+    #[inline]
+    fn clone(&self) -> Self {
+        // new references can only be created from existing ones, so no synchronization is needed
+        let old = self.inner().count.fetch_add(1, Ordering::Relaxed);
+        if old > MAX_REFCOUNT {
+            abort();
+        }
+        ThinArc {
+            ptr: self.ptr,
+            _owned: PhantomData,
+        }
+    }
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<Header, Tail> Deref for ThinArc<Header, Tail> {
+    type Target = DynStruct<Header, Tail>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.value_ptr() }
+    }
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<Header, Tail> Drop for ThinArc<Header, Tail> {
+    fn drop(&mut self) {
+        if self.inner().count.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        // synchronize with the decrements of all other references before destroying the contents
+        atomic::fence(Ordering::Acquire);
+
+        let len = self.inner().len;
+        unsafe {
+            core::ptr::drop_in_place(self.value_ptr());
+            // the layout was valid when we allocated it, so it still is
+            let layout = thin_layout::<Header, Tail, ThinArcInner<Header, Tail>>(len).unwrap();
+            dealloc(self.ptr.as_ptr().cast(), layout);
+        }
+    }
+}
+
+// SAFETY: same as for `Arc`, the contents are shared between threads and may be dropped on any of
+// them
+#[cfg(target_has_atomic = "ptr")]
+unsafe impl<Header: Send + Sync, Tail: Send + Sync> Send for ThinArc<Header, Tail> {}
+#[cfg(target_has_atomic = "ptr")]
+unsafe impl<Header: Send + Sync, Tail: Send + Sync> Sync for ThinArc<Header, Tail> {}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<Header: fmt::Debug, Tail: fmt::Debug> fmt::Debug for ThinArc<Header, Tail> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Abort the process, also in `#![no_std]` where `std::process::abort` is unavailable.
+#[cfg(target_has_atomic = "ptr")]
+#[cold]
+fn abort() -> ! {
+    #[cfg(any(feature = "std", test))]
+    std::process::abort();
+
+    #[cfg(not(any(feature = "std", test)))]
+    {
+        // panicking while panicking aborts
+        struct Abort;
+        impl Drop for Abort {
+            fn drop(&mut self) {
+                panic!("reference count overflow");
+            }
+        }
+        let _abort = Abort;
+        panic!("reference count overflow");
+    }
+}
+
+/// Memory from the global allocator with room for a prefix of type `P`, owned by a `ThinBox` or
+/// `ThinArc` once initialized.
+struct ThinAlloc<P> {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    value_offset: usize,
+    _prefix: PhantomData<P>,
+}
+
+impl<P> ThinAlloc<P> {
+    fn allocate<Header, Tail>(len: usize, prefix: P) -> Result<Self, DynStructError> {
+        // the prefix is never zero-sized, so neither is the allocation
+        let layout = thin_layout::<Header, Tail, P>(len)?;
+        let ptr =
+            NonNull::new(unsafe { alloc(layout) }).ok_or(DynStructError::AllocError(layout))?;
+
+        unsafe { ptr.cast::<P>().as_ptr().write(prefix) }
+
+        Ok(ThinAlloc {
+            ptr,
+            layout,
+            value_offset: value_offset::<Header, Tail, P>(),
+            _prefix: PhantomData,
+        })
+    }
+
+    #[inline]
+    fn value_ptr(&mut self) -> *mut u8 {
+        unsafe { self.ptr.as_ptr().add(self.value_offset) }
+    }
+
+    /// Give up ownership of the memory, which is now owned by the returned pointer.
+    #[inline]
+    fn into_raw(self) -> *mut P {
+        let ptr = self.ptr.as_ptr().cast();
+        core::mem::forget(self);
+        ptr
+    }
+}
+
+unsafe impl<Header, Tail, Len: ThinLength> Allocation<Header, Tail> for ThinAlloc<Len> {
+    type Output = ThinBox<Header, Tail, Len>;
+    type Allocator = ();
+
+    #[inline]
+    fn new(len: usize, _: ()) -> Result<Self, DynStructError> {
+        if len > Len::MAX {
+            return Err(DynStructError::LengthOverflow { len, max: Len::MAX });
+        }
+        ThinAlloc::allocate::<Header, Tail>(len, Len::from_usize(len))
+    }
+
+    #[inline]
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.value_ptr()
+    }
+
+    #[inline]
+    unsafe fn finish(self, _len: usize) -> Self::Output {
+        ThinBox::from_raw(self.into_raw().cast())
+    }
+}
+
+#[cfg(target_has_atomic = "ptr")]
+unsafe impl<Header, Tail> Allocation<Header, Tail> for ThinAlloc<ThinArcInner<Header, Tail>> {
+    type Output = ThinArc<Header, Tail>;
+    type Allocator = ();
+
+    #[inline]
+    fn new(len: usize, _: ()) -> Result<Self, DynStructError> {
+        let inner = ThinArcInner {
+            count: AtomicUsize::new(1),
+            len,
+            _value: PhantomData,
+        };
+        ThinAlloc::allocate::<Header, Tail>(len, inner)
+    }
+
+    #[inline]
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.value_ptr()
+    }
+
+    #[inline]
+    unsafe fn finish(self, _len: usize) -> Self::Output {
+        ThinArc::from_raw(self.into_raw())
+    }
+}
+
+impl<P> Drop for ThinAlloc<P> {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::mem::size_of;
+    use core::sync::atomic::{AtomicPtr, Ordering};
+
+    #[test]
+    fn thin_pointer() {
+        assert_eq!(size_of::<ThinBox<u64, u8>>(), size_of::<usize>());
+        assert_eq!(
+            size_of::<Option<ThinBox<u64, u8, u8>>>(),
+            size_of::<usize>()
+        );
+
+        let mut thin = ThinBox::<_, _>::new((1u8, 2u64), [3u16, 4, 5]);
+        assert_eq!(thin.header, (1, 2));
+        thin.tail[1] = 10;
+        assert_eq!(&thin.tail, &[3, 10, 5]);
+
+        let atomic = AtomicPtr::new(ThinBox::into_raw(thin));
+        let thin =
+            unsafe { ThinBox::from_raw(atomic.swap(core::ptr::null_mut(), Ordering::AcqRel)) };
+        assert_eq!(&thin.tail, &[3, 10, 5]);
+
+        let zero = ThinBox::<(), (), u8>::new((), [(); 255]);
+        assert_eq!(zero.tail.len(), 255);
+    }
+
+    #[test]
+    fn length_type() {
+        let short = ThinBox::<u8, u32, u8>::from_slice(1, &[7; 255]);
+        assert_eq!(short.tail.len(), 255);
+
+        let too_long = ThinBox::<u8, u32, u8>::try_new(1, vec![0; 256]);
+        assert_eq!(
+            too_long.unwrap_err(),
+            DynStructError::LengthOverflow { len: 256, max: 255 }
+        );
+    }
+
+    #[test]
+    fn drop_contents() {
+        let counter = Rc::new(());
+        let thin = ThinBox::<_, _, u16>::new(Rc::clone(&counter), vec![Rc::clone(&counter); 3]);
+        assert_eq!(Rc::strong_count(&counter), 5);
+        drop(thin);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn thin_arc() {
+        assert_eq!(size_of::<ThinArc<u64, u8>>(), size_of::<usize>());
+
+        let mut a = ThinArc::new(1u8, [2u32, 3, 4]);
+        assert!(ThinArc::get_mut(&mut a).is_some());
+
+        let b = a.clone();
+        assert_eq!(ThinArc::strong_count(&a), 2);
+        assert!(ThinArc::ptr_eq(&a, &b));
+        assert!(ThinArc::get_mut(&mut a).is_none());
+
+        ThinArc::make_mut(&mut a).tail[0] = 20;
+        assert!(!ThinArc::ptr_eq(&a, &b));
+        assert_eq!(&a.tail, &[20, 3, 4]);
+        assert_eq!(&b.tail, &[2, 3, 4]);
+        assert_eq!(ThinArc::strong_count(&b), 1);
+
+        let raw = ThinArc::into_raw(b);
+        let b = unsafe { ThinArc::from_raw(raw) };
+        let shared = std::thread::spawn(move || b.tail.iter().sum::<u32>());
+        assert_eq!(shared.join().unwrap(), 9);
+
+        let zero = ThinArc::<(), ()>::from_slice((), &[(); 3]);
+        assert_eq!(zero.tail.len(), 3);
+    }
+
+    #[test]
+    fn thin_arc_drop() {
+        let counter = Rc::new(());
+        let a = ThinArc::new(Rc::clone(&counter), vec![Rc::clone(&counter); 2]);
+        let b = a.clone();
+        drop(a);
+        assert_eq!(Rc::strong_count(&counter), 4);
+        drop(b);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+}
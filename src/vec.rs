@@ -0,0 +1,572 @@
+//! A growable collection of `DynStruct`s packed into a single buffer.
+
+use crate::{dyn_struct_ptr, Allocation, Chunk, DynStruct, DynStructError, GlobalBox};
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+use core::ops::{Index, IndexMut};
+
+/// A list of `DynStruct`s whose tails may all have different lengths, stored back to back in one
+/// buffer.
+///
+/// A `Vec<Box<DynStruct<Header, Tail>>>` needs one allocation and a fat pointer per element. A
+/// `DynStructVec` instead writes every element into a single growing buffer, and keeps the offset
+/// and length of each in a separate index. Elements never straddle any padding other than their
+/// own, since the size of a `DynStruct` is always a multiple of its alignment.
+///
+/// ```
+/// # use dyn_struct::DynStructVec;
+/// let mut values = DynStructVec::new();
+/// values.push("first", [1u32, 2, 3]);
+/// values.push("second", []);
+/// values.push("third", [4]);
+///
+/// assert_eq!(values[0].header, "first");
+/// assert_eq!(&values[2].tail, &[4]);
+///
+/// let removed = values.remove(1);
+/// assert_eq!(removed.header, "second");
+/// let headers: Vec<_> = values.iter().map(|value| value.header).collect();
+/// assert_eq!(headers, ["first", "third"]);
+/// ```
+pub struct DynStructVec<Header, Tail> {
+    /// The elements, with the `Chunk`s giving the buffer the alignment of a `DynStruct`.
+    chunks: Vec<MaybeUninit<Chunk<Header, Tail>>>,
+    /// Where each element is located in `chunks`, in the same order as the elements themselves.
+    index: Vec<Entry>,
+    _owned: PhantomData<DynStruct<Header, Tail>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    /// The offset of the element, in chunks.
+    offset: usize,
+    /// The length of the tail.
+    len: usize,
+}
+
+/// An iterator over the elements of a [`DynStructVec`].
+pub struct Iter<'a, Header, Tail> {
+    data: *const u8,
+    index: core::slice::Iter<'a, Entry>,
+    _marker: PhantomData<&'a DynStruct<Header, Tail>>,
+}
+
+/// An iterator over mutable references to the elements of a [`DynStructVec`].
+pub struct IterMut<'a, Header, Tail> {
+    data: *mut u8,
+    index: core::slice::Iter<'a, Entry>,
+    _marker: PhantomData<&'a mut DynStruct<Header, Tail>>,
+}
+
+impl<Header, Tail> DynStructVec<Header, Tail> {
+    /// Create an empty `DynStructVec`. Does not allocate until the first element is pushed.
+    #[inline]
+    pub const fn new() -> Self {
+        DynStructVec {
+            chunks: Vec::new(),
+            index: Vec::new(),
+            _owned: PhantomData,
+        }
+    }
+
+    /// Returns the number of elements.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Returns `true` if there are no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Append an element to the end of the buffer, initialized from the header and an iterator
+    /// over the tail.
+    #[inline]
+    pub fn push<I>(&mut self, header: Header, tail: I)
+    where
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        self.try_push(header, tail)
+            .unwrap_or_else(|error| error.raise::<I::IntoIter>())
+    }
+
+    /// Same as [`DynStructVec::push`], but returns an error instead of panicking if the iterator
+    /// yields a different number of items than it reports, and instead of aborting if the buffer
+    /// cannot grow. The `DynStructVec` is left unchanged on errors.
+    pub fn try_push<I>(&mut self, header: Header, tail: I) -> Result<(), DynStructError>
+    where
+        I: IntoIterator<Item = Tail>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let tail = tail.into_iter();
+        let entry = Entry {
+            offset: self.chunks.len(),
+            len: tail.len(),
+        };
+
+        // make room in the index first, so that we never have to undo writing the element
+        self.index.reserve(1);
+        DynStruct::write_iter::<VecSlot<Header, Tail>, _>(header, tail, &mut self.chunks)?;
+        self.index.push(entry);
+        Ok(())
+    }
+
+    /// Returns the element at `index`, or `None` if it is out of bounds.
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<&DynStruct<Header, Tail>> {
+        let entry = self.index.get(index)?;
+        Some(unsafe { &*Self::element_ptr(self.chunks.as_ptr() as *mut u8, entry) })
+    }
+
+    /// Returns the element at `index` mutably, or `None` if it is out of bounds.
+    #[inline]
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut DynStruct<Header, Tail>> {
+        let entry = self.index.get(index)?;
+        Some(unsafe { &mut *Self::element_ptr(self.chunks.as_mut_ptr().cast(), entry) })
+    }
+
+    /// Iterate over the elements in order.
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, Header, Tail> {
+        Iter {
+            data: self.chunks.as_ptr().cast(),
+            index: self.index.iter(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Iterate mutably over the elements in order.
+    #[inline]
+    pub fn iter_mut(&mut self) -> IterMut<'_, Header, Tail> {
+        IterMut {
+            data: self.chunks.as_mut_ptr().cast(),
+            index: self.index.iter(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Remove the element at `index` and return it in a `Box`, moving all elements after it
+    /// forward to close the gap.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> Box<DynStruct<Header, Tail>> {
+        let entry = self.index[index];
+        let removed = self.take(entry);
+
+        let size = Self::chunks_for(entry.len);
+        let end = entry.offset + size;
+        unsafe {
+            let start = self.chunks.as_mut_ptr().add(entry.offset);
+            start.copy_from(start.add(size), self.chunks.len() - end);
+            self.chunks.set_len(self.chunks.len() - size);
+        }
+
+        self.index.remove(index);
+        for entry in &mut self.index[index..] {
+            entry.offset -= size;
+        }
+
+        removed
+    }
+
+    /// Remove the element at `index` and return it in a `Box`, moving the last element into its
+    /// place.
+    ///
+    /// Unlike [`Vec::swap_remove`], this is only `O(1)` if the removed and the last element have
+    /// the same size. Otherwise the elements in between still have to be moved to fit the last
+    /// element into the gap, but the order of the others is preserved.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn swap_remove(&mut self, index: usize) -> Box<DynStruct<Header, Tail>> {
+        let entry = self.index[index];
+        let removed = self.take(entry);
+
+        let last = *self.index.last().unwrap();
+        let size = Self::chunks_for(entry.len);
+        let last_size = Self::chunks_for(last.len);
+
+        if index + 1 == self.index.len() {
+            // nothing to move, the removed element was the last one
+        } else if size == last_size {
+            unsafe {
+                let chunks = self.chunks.as_mut_ptr();
+                chunks
+                    .add(entry.offset)
+                    .copy_from_nonoverlapping(chunks.add(last.offset), size);
+            }
+        } else {
+            // [removed][between][last] -> [last][removed][between] -> [last][between][removed]
+            let chunks = &mut self.chunks[entry.offset..];
+            chunks.rotate_right(last_size);
+            chunks[last_size..].rotate_left(size);
+
+            let between = index + 1..self.index.len() - 1;
+            for entry in &mut self.index[between] {
+                entry.offset = entry.offset + last_size - size;
+            }
+        }
+
+        self.index.swap_remove(index);
+        if let Some(moved) = self.index.get_mut(index) {
+            moved.offset = entry.offset;
+        }
+        unsafe { self.chunks.set_len(self.chunks.len() - size) };
+
+        removed
+    }
+
+    /// Remove and drop all elements, keeping the allocated capacity.
+    pub fn clear(&mut self) {
+        // forget about the elements before dropping them, so that a panicking destructor leaks the
+        // remaining ones instead of dropping them twice
+        unsafe { self.chunks.set_len(0) };
+        let data = self.chunks.as_mut_ptr().cast();
+        for entry in self.index.drain(..) {
+            unsafe { core::ptr::drop_in_place(dyn_struct_ptr::<Header, Tail>(data, entry.len)) };
+        }
+    }
+
+    /// Move an element out into a new `Box`, leaving its bytes behind as garbage.
+    fn take(&mut self, entry: Entry) -> Box<DynStruct<Header, Tail>> {
+        let alloc = GlobalBox::allocate::<Header, Tail>(entry.len, false)
+            .unwrap_or_else(|error| error.raise::<()>());
+
+        // SAFETY: both have the layout of a `DynStruct` of this length, and ownership of the header
+        // and tail moves to the `Box` (the bytes left behind are never read again)
+        unsafe {
+            let src = Self::element_ptr(self.chunks.as_mut_ptr().cast(), &entry);
+            alloc
+                .ptr
+                .as_ptr()
+                .copy_from_nonoverlapping(src.cast(), alloc.layout.size());
+            <GlobalBox as Allocation<Header, Tail>>::finish(alloc, entry.len)
+        }
+    }
+
+    fn element_ptr(data: *mut u8, entry: &Entry) -> *mut DynStruct<Header, Tail> {
+        let offset = entry.offset * size_of::<Chunk<Header, Tail>>();
+        dyn_struct_ptr(unsafe { data.add(offset) }, entry.len)
+    }
+
+    /// The number of chunks taken up by an element with a tail of `len` elements.
+    fn chunks_for(len: usize) -> usize {
+        // we have allocated an element of this length before, so the layout is valid
+        let layout = DynStruct::<Header, Tail>::layout(len).unwrap();
+        layout.size() / size_of::<Chunk<Header, Tail>>()
+    }
+}
+
+impl<Header, Tail> Drop for DynStructVec<Header, Tail> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<Header, Tail> Default for DynStructVec<Header, Tail> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Header, Tail> Index<usize> for DynStructVec<Header, Tail> {
+    type Output = DynStruct<Header, Tail>;
+
+    #[inline]
+    #[track_caller]
+    fn index(&self, index: usize) -> &Self::Output {
+        let len = self.len();
+        self.get(index).unwrap_or_else(|| out_of_bounds(index, len))
+    }
+}
+
+impl<Header, Tail> IndexMut<usize> for DynStructVec<Header, Tail> {
+    #[inline]
+    #[track_caller]
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        let len = self.len();
+        self.get_mut(index)
+            .unwrap_or_else(|| out_of_bounds(index, len))
+    }
+}
+
+#[cold]
+#[track_caller]
+fn out_of_bounds(index: usize, len: usize) -> ! {
+    panic!(
+        "index out of bounds: the len is {} but the index is {}",
+        len, index
+    )
+}
+
+impl<Header: fmt::Debug, Tail: fmt::Debug> fmt::Debug for DynStructVec<Header, Tail> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, Header, Tail> IntoIterator for &'a DynStructVec<Header, Tail> {
+    type Item = &'a DynStruct<Header, Tail>;
+    type IntoIter = Iter<'a, Header, Tail>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, Header, Tail> IntoIterator for &'a mut DynStructVec<Header, Tail> {
+    type Item = &'a mut DynStruct<Header, Tail>;
+    type IntoIter = IterMut<'a, Header, Tail>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+/// Implements the iterator traits for `Iter` and `IterMut`, which only differ in mutability.
+macro_rules! impl_iter {
+    ($iter:ident, ($($ty:tt)*), ($($ref:tt)*)) => {
+        impl<'a, Header, Tail> $iter<'a, Header, Tail> {
+            #[inline]
+            fn element(&self, entry: &Entry) -> $($ty)* DynStruct<Header, Tail> {
+                let offset = entry.offset * size_of::<Chunk<Header, Tail>>();
+                // SAFETY: the entries point to distinct, initialized elements, which are borrowed
+                // for `'a`
+                unsafe { $($ref)* *dyn_struct_ptr(self.data.add(offset) as *mut u8, entry.len) }
+            }
+        }
+
+        impl<'a, Header, Tail> Iterator for $iter<'a, Header, Tail> {
+            type Item = $($ty)* DynStruct<Header, Tail>;
+
+            #[inline]
+            fn next(&mut self) -> Option<Self::Item> {
+                let entry = self.index.next()?;
+                Some(self.element(entry))
+            }
+
+            #[inline]
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                self.index.size_hint()
+            }
+        }
+
+        impl<Header, Tail> DoubleEndedIterator for $iter<'_, Header, Tail> {
+            #[inline]
+            fn next_back(&mut self) -> Option<Self::Item> {
+                let entry = self.index.next_back()?;
+                Some(self.element(entry))
+            }
+        }
+
+        impl<Header, Tail> ExactSizeIterator for $iter<'_, Header, Tail> {}
+
+        impl<Header, Tail> FusedIterator for $iter<'_, Header, Tail> {}
+    };
+}
+
+impl_iter!(Iter, (&'a), (&));
+impl_iter!(IterMut, (&'a mut), (&mut));
+
+// SAFETY: the iterators behave like references to the elements
+unsafe impl<Header: Sync, Tail: Sync> Send for Iter<'_, Header, Tail> {}
+unsafe impl<Header: Sync, Tail: Sync> Sync for Iter<'_, Header, Tail> {}
+unsafe impl<Header: Send, Tail: Send> Send for IterMut<'_, Header, Tail> {}
+unsafe impl<Header: Sync, Tail: Sync> Sync for IterMut<'_, Header, Tail> {}
+
+/// The spare capacity at the end of the buffer of a [`DynStructVec`]. Once initialized, the
+/// element becomes part of the buffer.
+struct VecSlot<'a, Header, Tail> {
+    chunks: &'a mut Vec<MaybeUninit<Chunk<Header, Tail>>>,
+    size: usize,
+}
+
+unsafe impl<'a, Header, Tail> Allocation<Header, Tail> for VecSlot<'a, Header, Tail> {
+    type Output = ();
+    type Allocator = &'a mut Vec<MaybeUninit<Chunk<Header, Tail>>>;
+
+    fn new(len: usize, chunks: Self::Allocator) -> Result<Self, DynStructError> {
+        let layout = DynStruct::<Header, Tail>::layout(len)?;
+        let size = layout.size() / size_of::<Chunk<Header, Tail>>();
+
+        if chunks.try_reserve(size).is_err() {
+            let required = chunks.len().checked_add(size);
+            return Err(match required.map(Layout::array::<Chunk<Header, Tail>>) {
+                Some(Ok(layout)) => DynStructError::AllocError(layout),
+                _ => DynStructError::SizeOverflow,
+            });
+        }
+
+        Ok(VecSlot { chunks, size })
+    }
+
+    #[inline]
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        let len = self.chunks.len();
+        unsafe { self.chunks.as_mut_ptr().add(len).cast() }
+    }
+
+    #[inline]
+    unsafe fn finish(self, _len: usize) -> Self::Output {
+        let len = self.chunks.len();
+        self.chunks.set_len(len + self.size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use alloc::string::String;
+
+    #[test]
+    fn push_and_get() {
+        let mut values = DynStructVec::new();
+        assert!(values.is_empty());
+        values.push(1u8, [1u64, 2, 3]);
+        values.push(2, []);
+        values.push(3, [4]);
+
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0].header, 1);
+        assert_eq!(&values[0].tail, &[1, 2, 3]);
+        assert!(values[1].tail.is_empty());
+        assert_eq!(values.get(2), Some(&*DynStruct::new(3, [4])));
+        assert!(values.get(3).is_none());
+
+        // the elements are packed together
+        assert_eq!(
+            values.chunks.len() * size_of::<Chunk<u8, u64>>(),
+            32 + 8 + 16
+        );
+
+        for value in &mut values {
+            value.header *= 10;
+            value.tail.reverse();
+        }
+        values.get_mut(1).unwrap().header += 1;
+
+        let all: Vec<_> = values
+            .iter()
+            .rev()
+            .map(|value| (value.header, value.tail.to_vec()))
+            .collect();
+        assert_eq!(all, [(30, vec![4]), (21, vec![]), (10, vec![3, 2, 1])]);
+    }
+
+    #[test]
+    fn remove() {
+        let mut values = DynStructVec::new();
+        for i in 0..5usize {
+            values.push(i, (0..i).map(|j| String::from("x").repeat(j)));
+        }
+
+        let removed = values.remove(1);
+        assert_eq!(removed.header, 1);
+        assert_eq!(&removed.tail, &[""]);
+
+        // sizes differ from the last element
+        let removed = values.swap_remove(0);
+        assert_eq!(removed.header, 0);
+        assert!(removed.tail.is_empty());
+        let headers: Vec<_> = values.iter().map(|value| value.header).collect();
+        assert_eq!(headers, [4, 2, 3]);
+        assert_eq!(values[0].tail[3], "xxx");
+        assert_eq!(values[2].tail[2], "xx");
+
+        // same size as the last element
+        values.push(5, vec![String::new(); 3]);
+        let removed = values.swap_remove(2);
+        assert_eq!(removed.header, 3);
+        let headers: Vec<_> = values.iter().map(|value| value.header).collect();
+        assert_eq!(headers, [4, 2, 5]);
+
+        // the last element itself
+        assert_eq!(values.swap_remove(2).header, 5);
+        assert_eq!(values.remove(1).header, 2);
+        assert_eq!(values.remove(0).tail.len(), 4);
+        assert!(values.is_empty());
+        assert!(values.chunks.is_empty());
+    }
+
+    #[test]
+    fn drop_elements() {
+        let counter = Rc::new(());
+        let mut values = DynStructVec::new();
+        for len in 0..4 {
+            values.push(counter.clone(), vec![counter.clone(); len]);
+        }
+        assert_eq!(Rc::strong_count(&counter), 1 + 4 + 6);
+
+        drop(values.remove(2));
+        assert_eq!(Rc::strong_count(&counter), 1 + 3 + 4);
+        values.clear();
+        assert_eq!(Rc::strong_count(&counter), 1);
+
+        values.push(counter.clone(), [counter.clone()]);
+        drop(values);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn lying_iterator() {
+        struct Liar<I>(I);
+
+        impl<I: Iterator> Iterator for Liar<I> {
+            type Item = I::Item;
+
+            fn next(&mut self) -> Option<I::Item> {
+                self.0.next()
+            }
+        }
+
+        impl<I: Iterator> ExactSizeIterator for Liar<I> {
+            fn len(&self) -> usize {
+                2
+            }
+        }
+
+        let counter = Rc::new(());
+        let mut values = DynStructVec::new();
+        values.push(0, [counter.clone()]);
+
+        let tail = Liar(vec![counter.clone(); 3].into_iter());
+        assert_eq!(
+            values.try_push(1, tail),
+            Err(DynStructError::LengthMismatch {
+                expected: 2,
+                actual: 3
+            })
+        );
+        assert_eq!(Rc::strong_count(&counter), 2);
+        assert_eq!(values.len(), 1);
+        assert_eq!(
+            values.chunks.len(),
+            DynStructVec::<i32, Rc<()>>::chunks_for(1)
+        );
+    }
+
+    #[test]
+    fn zero_sized() {
+        let mut values = DynStructVec::<(), ()>::new();
+        values.push((), [(); 3]);
+        values.push((), []);
+        assert_eq!(values[0].tail.len(), 3);
+        assert_eq!(values.swap_remove(0).tail.len(), 3);
+        assert_eq!(values[0].tail.len(), 0);
+    }
+}
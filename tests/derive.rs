@@ -1,3 +1,7 @@
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+// the baseline tests compare against `true`, which is kept as written
+#![allow(clippy::bool_assert_comparison)]
+
 use dyn_struct::DynStruct;
 
 #[test]
@@ -46,6 +50,58 @@ fn readme() {
     assert_eq!(&foo.dynamic, &[4, 5, 6, 7]);
 }
 
+#[test]
+fn from_iter() {
+    #[repr(C)]
+    #[derive(DynStruct)]
+    struct Word<'a> {
+        pub source: &'a str,
+        pub letters: [char],
+    }
+
+    let word = Word::from_iter("Hello", "Hello".chars().filter(|c| c.is_lowercase()));
+    assert_eq!(word.source, "Hello");
+    assert_eq!(&word.letters, ['e', 'l', 'l', 'o']);
+}
+
+#[test]
+fn try_from_iter() {
+    #[repr(C)]
+    #[derive(DynStruct)]
+    struct Record {
+        pub id: u32,
+        pub values: [u16],
+    }
+
+    let record = Record::try_from_iter(7, "10,20,30".split(',').map(str::parse)).unwrap();
+    assert_eq!(record.id, 7);
+    assert_eq!(&record.values, [10, 20, 30]);
+
+    let error = Record::try_from_iter(8, "10,-20,30".split(',').map(str::parse));
+    assert!(error.is_err());
+}
+
+#[test]
+fn clone() {
+    #[repr(C)]
+    #[derive(Debug, DynStruct)]
+    #[dyn_struct(clone)]
+    struct Foo<'a, T> {
+        pub name: String,
+        pub text: &'a str,
+        pub values: [T],
+    }
+
+    let foo = Foo::new(String::from("foo"), "hello", vec![vec![1], vec![2, 3]]);
+    let clone = foo.clone();
+    assert_eq!(clone.name, "foo");
+    assert_eq!(clone.text, "hello");
+    assert_eq!(&clone.values, &foo.values);
+
+    let boxed = foo.clone_box();
+    assert_eq!(boxed.values.len(), 2);
+}
+
 #[test]
 fn non_copy_with_drop() {
     use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
@@ -88,3 +144,250 @@ fn non_copy_with_drop() {
         "dropping DynStruct should result in drop"
     );
 }
+
+#[test]
+fn reference_counted() {
+    #[repr(C)]
+    #[derive(Debug, DynStruct)]
+    struct Foo {
+        pub inner: u32,
+        pub values: [u32],
+    }
+
+    let foo = Foo::new_arc(14, vec![1, 2, 3, 4]);
+    let shared = std::sync::Arc::clone(&foo);
+    assert_eq!(shared.inner, 14);
+    assert_eq!(&shared.values, [1, 2, 3, 4]);
+
+    let foo = Foo::new_rc(7, 0..2);
+    assert_eq!(foo.inner, 7);
+    assert_eq!(&foo.values, [0, 1]);
+}
+
+#[test]
+fn thin_arc() {
+    use dyn_struct::{DynStructLike, ThinArc};
+
+    #[repr(C)]
+    #[derive(Debug, DynStruct)]
+    #[dyn_struct(clone)]
+    struct Node<'a> {
+        pub label: &'a str,
+        pub depth: u8,
+        pub children: [u64],
+    }
+
+    let mut node = Node::new_thin_arc("root", 0, vec![1, 2, 3]);
+    let shared = node.clone();
+    Node::from_dyn_struct_mut(ThinArc::make_mut(&mut node)).depth = 1;
+
+    let view = Node::from_dyn_struct(&node);
+    assert_eq!(view.label, "root");
+    assert_eq!(view.depth, 1);
+    assert_eq!(&view.children, [1, 2, 3]);
+    assert_eq!(Node::from_dyn_struct(&shared).depth, 0);
+
+    let boxed = Node::new("leaf", 2, []);
+    assert_eq!(boxed.as_dyn_struct().tail.len(), 0);
+}
+
+#[test]
+#[cfg(any(feature = "allocator_api", feature = "allocator-api2"))]
+fn new_in() {
+    use std::alloc::System;
+
+    #[repr(C)]
+    #[derive(Debug, DynStruct)]
+    #[dyn_struct(new_in)]
+    struct Foo {
+        pub inner: u32,
+        pub values: [u32],
+    }
+
+    let foo = Foo::new_in(14, vec![1, 2, 3, 4], System);
+    assert_eq!(foo.inner, 14);
+    assert_eq!(&foo.values, [1, 2, 3, 4]);
+}
+
+#[test]
+fn into_parts() {
+    #[repr(C)]
+    #[derive(DynStruct)]
+    struct Message {
+        pub sender: String,
+        pub id: u32,
+        pub lines: [String],
+    }
+
+    let message = Message::new("alice".to_string(), 3, vec!["hi".to_string()]);
+    let (sender, id, lines) = message.into_parts();
+    assert_eq!(sender, "alice");
+    assert_eq!(id, 3);
+    assert_eq!(lines, ["hi"]);
+}
+
+#[test]
+fn arena() {
+    use dyn_struct::DynArena;
+    use std::rc::Rc;
+
+    #[repr(C)]
+    #[derive(DynStruct)]
+    struct Request<'a> {
+        pub path: &'a str,
+        pub id: u32,
+        pub params: [Rc<String>],
+    }
+
+    let path = String::from("/index");
+    let param = Rc::new(String::from("key=value"));
+
+    let arena = DynArena::new();
+    let requests: Vec<&mut Request> = (0..3)
+        .map(|id| Request::new_in_arena(&arena, &path, id, vec![param.clone(); id as usize]))
+        .collect();
+    assert_eq!(Rc::strong_count(&param), 1 + 3);
+
+    for (id, request) in requests.into_iter().enumerate() {
+        assert_eq!(request.path, "/index");
+        assert_eq!(request.id, id as u32);
+        assert_eq!(request.params.len(), id);
+        request.params.reverse();
+    }
+
+    drop(arena);
+    assert_eq!(Rc::strong_count(&param), 1);
+}
+
+#[test]
+fn homogeneous_header() {
+    use dyn_struct::HomogeneousHeader;
+
+    #[repr(C)]
+    #[derive(HomogeneousHeader)]
+    struct RecordHeader {
+        kind: u32,
+        flags: [u32; 2],
+        len: u32,
+    }
+
+    assert_eq!(<RecordHeader as HomogeneousHeader<u32>>::LEN, 4);
+
+    let mut buffer = [1u32, 2, 3, 2, 10, 20, 99];
+    let (record, rest) =
+        DynStruct::<RecordHeader, u32>::split_slice_view_mut(&mut buffer, 2).unwrap();
+    assert_eq!(record.header.kind, 1);
+    assert_eq!(record.header.flags, [2, 3]);
+    assert_eq!(&record.tail, [10, 20]);
+    record.header.len = 20;
+    assert_eq!(rest, [99]);
+    assert_eq!(buffer[3], 20);
+
+    #[repr(C)]
+    #[derive(HomogeneousHeader)]
+    struct Pair<T>(T, T);
+
+    let pair = DynStruct::<Pair<u8>, u8>::slice_view(b"abc");
+    assert_eq!((pair.header.0, pair.header.1), (b'a', b'b'));
+    assert_eq!(&pair.tail, b"c");
+}
+
+#[test]
+#[cfg(any(feature = "bytemuck", feature = "zerocopy"))]
+fn bytes() {
+    #[repr(C)]
+    #[derive(DynStruct)]
+    #[dyn_struct(bytes)]
+    struct Packet {
+        pub kind: u16,
+        pub flags: u16,
+        pub payload: [u32],
+    }
+
+    #[repr(C, align(4))]
+    struct Aligned([u8; 12]);
+
+    let mut bytes = Aligned([1, 0, 2, 0, 3, 0, 0, 0, 4, 0, 0, 0]);
+    let packet = Packet::from_bytes_mut(&mut bytes.0).unwrap();
+    assert_eq!(packet.kind, u16::from_ne_bytes([1, 0]));
+    assert_eq!(packet.payload.len(), 2);
+    packet.flags = 0;
+    assert_eq!(packet.as_bytes(), [1, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0]);
+
+    let packet = Packet::new(1, 2, [3]);
+    assert_eq!(Packet::from_bytes(packet.as_bytes()).unwrap().payload, [3]);
+
+    #[cfg(target_endian = "little")]
+    {
+        let mut stream = Vec::new();
+        packet.write_to(&mut stream).unwrap();
+        assert_eq!(stream, [1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 2, 0, 3, 0, 0, 0]);
+
+        Packet::new(4, 5, [6, 7]).write_to(&mut stream).unwrap();
+        let mut reader = &stream[..];
+        let first = Packet::read_from(&mut reader).unwrap();
+        let second = Packet::read_from_with_max_len(&mut reader, 2).unwrap();
+        assert_eq!((first.kind, first.flags, &first.payload), (1, 2, &[3][..]));
+        assert_eq!(
+            (second.kind, second.flags, &second.payload),
+            (4, 5, &[6, 7][..])
+        );
+        assert!(reader.is_empty());
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn serde() {
+    #[repr(C)]
+    #[derive(Debug, DynStruct)]
+    #[dyn_struct(serde)]
+    struct Entry<'a, T> {
+        pub id: u64,
+        pub name: &'a str,
+        pub values: [T],
+    }
+
+    let entry = Entry::new(7, "seven", vec![1i16, -2, 3]);
+    let json = serde_json::to_string(&entry).unwrap();
+    assert_eq!(json, r#"{"id":7,"name":"seven","values":[1,-2,3]}"#);
+
+    let back: Box<Entry<i16>> = serde_json::from_str(&json).unwrap();
+    assert_eq!((back.id, back.name), (7, "seven"));
+    assert_eq!(&back.values, [1, -2, 3]);
+
+    let back: Box<Entry<i16>> =
+        serde_json::from_str(r#"{"values":[4],"name":"x","id":1}"#).unwrap();
+    assert_eq!((back.id, back.name, &back.values), (1, "x", &[4][..]));
+
+    let bytes = bincode::serialize(&entry).unwrap();
+    let back: Box<Entry<i16>> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(&back.values, &entry.values);
+}
+
+#[test]
+#[cfg(feature = "rkyv")]
+fn rkyv() {
+    use rkyv::rancor::Error;
+
+    #[repr(C)]
+    #[derive(DynStruct)]
+    #[dyn_struct(rkyv)]
+    struct Snapshot<T> {
+        pub id: u32,
+        pub name: String,
+        pub samples: [T],
+    }
+
+    let snapshot = Snapshot::new(3, String::from("sensor"), vec![1.5f32, -2.0]);
+    let bytes = rkyv::to_bytes::<Error>(&snapshot).unwrap();
+
+    let archived = rkyv::access::<rkyv::Archived<Box<Snapshot<f32>>>, Error>(&bytes).unwrap();
+    assert_eq!(archived.header.id, 3);
+    assert_eq!(archived.header.name, "sensor");
+    assert_eq!(archived.tail, [1.5, -2.0]);
+
+    let back = rkyv::deserialize::<Box<Snapshot<f32>>, Error>(archived).unwrap();
+    assert_eq!((back.id, back.name.as_str()), (3, "sensor"));
+    assert_eq!(&back.samples, [1.5, -2.0]);
+}